@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local, Utc};
+use clap::{Args, Subcommand, ValueEnum, ValueHint::FilePath};
+use hustsync_config_parser::CtlConfig;
+use hustsync_internal::logger::init_logger;
+use hustsync_internal::msg::{ClientCmd, CmdVerb, MirrorStatus, WorkerStatus};
+use hustsync_internal::status_web::WebMirrorStatus;
+use hustsync_internal::util::{create_http_client, get_json, post_json};
+use reqwest::blocking::{Client, Response};
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::debug;
+
+#[derive(Args, Debug)]
+pub(crate) struct CtlArgs {
+    /// Read ctl configurations from `FILE`
+    #[arg(short, long, value_name = "config", value_hint = FilePath)]
+    config: Option<PathBuf>,
+    /// The manager server address
+    #[arg(short, long, value_name = "addr")]
+    manager: Option<String>,
+    /// The manager server port
+    #[arg(short, long, value_name = "port")]
+    port: Option<u16>,
+    /// Trust root CA cert file `FILE`, and talk to the manager over https
+    #[arg(long, value_name = "ca-cert", value_hint = FilePath)]
+    ca_cert: Option<PathBuf>,
+    /// How to print the results
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+    /// Enable debug logging
+    #[arg(long)]
+    debug: bool,
+    #[command(subcommand)]
+    command: CtlCommands,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommands {
+    /// List jobs of the given workers, or of all workers with --all
+    List {
+        /// List jobs of all workers
+        #[arg(short, long)]
+        all: bool,
+        workers: Vec<String>,
+    },
+    /// List the registered workers
+    Workers,
+    /// Start a job
+    Start {
+        #[command(flatten)]
+        job: JobArgs,
+        /// Start the job right away, even when the worker already runs as many
+        /// syncs as its concurrent limit allows
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Stop a job
+    Stop(JobArgs),
+    /// Restart a job
+    Restart(JobArgs),
+    /// Disable a job
+    Disable(JobArgs),
+    /// Tell a worker to reload its configurations
+    Reload { worker: String },
+    /// Remove the disabled jobs from the manager
+    Flush,
+    /// Set the size of a mirror
+    SetSize {
+        #[command(flatten)]
+        job: JobArgs,
+        size: String,
+    },
+}
+
+#[derive(Args, Debug)]
+struct JobArgs {
+    worker: String,
+    mirror: String,
+}
+
+fn load_ctl_config(args: &CtlArgs) -> Result<CtlConfig, Box<dyn Error>> {
+    let mut config = match &args.config {
+        Some(path) => hustsync_config_parser::parse_config(path)?,
+        None => CtlConfig::default(),
+    };
+
+    // command line flags take precedence over the config file
+    if let Some(addr) = &args.manager {
+        config.manager_addr = Some(addr.clone());
+    }
+    if let Some(port) = args.port {
+        config.manager_port = Some(port);
+    }
+    if let Some(ca_cert) = &args.ca_cert {
+        config.ca_cert = Some(ca_cert.to_string_lossy().into_owned());
+    }
+    Ok(config)
+}
+
+/// Talks to the manager on behalf of `hustsync-cli ctl`.
+struct Ctl {
+    base_url: String,
+    client: Client,
+    format: OutputFormat,
+}
+
+impl Ctl {
+    fn new(config: &CtlConfig, format: OutputFormat) -> Result<Self, Box<dyn Error>> {
+        let defaults = CtlConfig::default();
+        let addr = config
+            .manager_addr
+            .as_deref()
+            .or(defaults.manager_addr.as_deref())
+            .unwrap_or_default();
+        let port = config
+            .manager_port
+            .or(defaults.manager_port)
+            .unwrap_or_default();
+        let ca_cert = config.ca_cert.as_deref().filter(|c| !c.is_empty());
+        let scheme = if ca_cert.is_some() { "https" } else { "http" };
+
+        Ok(Ctl {
+            base_url: format!("{}://{}:{}", scheme, addr, port),
+            client: create_http_client(ca_cert)?,
+            format,
+        })
+    }
+
+    fn run(&self, command: CtlCommands) -> Result<(), Box<dyn Error>> {
+        match command {
+            CtlCommands::List { all, workers } => self.list_jobs(all, &workers),
+            CtlCommands::Workers => self.list_workers(),
+            CtlCommands::Start { job, force } => {
+                let options = HashMap::from([("force".to_string(), force)]);
+                self.send_cmd(CmdVerb::Start, &job.worker, &job.mirror, options)
+            }
+            CtlCommands::Stop(job) => {
+                self.send_cmd(CmdVerb::Stop, &job.worker, &job.mirror, HashMap::new())
+            }
+            CtlCommands::Restart(job) => {
+                self.send_cmd(CmdVerb::Restart, &job.worker, &job.mirror, HashMap::new())
+            }
+            CtlCommands::Disable(job) => {
+                self.send_cmd(CmdVerb::Disable, &job.worker, &job.mirror, HashMap::new())
+            }
+            CtlCommands::Reload { worker } => {
+                self.send_cmd(CmdVerb::Reload, &worker, "", HashMap::new())
+            }
+            CtlCommands::Flush => self.flush(),
+            CtlCommands::SetSize { job, size } => self.set_size(&job, size),
+        }
+    }
+
+    fn list_jobs(&self, all: bool, workers: &[String]) -> Result<(), Box<dyn Error>> {
+        let jobs: Vec<WebMirrorStatus> = if all {
+            get_json(&format!("{}/jobs", self.base_url), Some(&self.client))?
+        } else if workers.is_empty() {
+            return Err("list needs at least one worker or the --all flag".into());
+        } else {
+            let mut jobs = Vec::new();
+            for worker in workers {
+                let url = format!("{}/workers/{}/jobs", self.base_url, worker);
+                let statuses: Vec<MirrorStatus> = get_json(&url, Some(&self.client))
+                    .map_err(|e| format!("failed to get jobs of worker {}: {}", worker, e))?;
+                jobs.extend(statuses.into_iter().map(WebMirrorStatus::from));
+            }
+            jobs
+        };
+        self.print(&jobs, || render_jobs(&jobs))
+    }
+
+    fn list_workers(&self) -> Result<(), Box<dyn Error>> {
+        let workers: Vec<WorkerStatus> =
+            get_json(&format!("{}/workers", self.base_url), Some(&self.client))?;
+        self.print(&workers, || render_workers(&workers))
+    }
+
+    fn send_cmd(
+        &self,
+        cmd: CmdVerb,
+        worker: &str,
+        mirror: &str,
+        options: HashMap<String, bool>,
+    ) -> Result<(), Box<dyn Error>> {
+        let client_cmd = ClientCmd {
+            options,
+            args: Vec::new(),
+            mirror_id: mirror.to_string(),
+            worker_id: worker.to_string(),
+            cmd,
+        };
+        debug!("sending {:?} to the manager", client_cmd);
+        let resp = post_json(
+            &format!("{}/cmd", self.base_url),
+            &client_cmd,
+            Some(&self.client),
+        )?;
+        self.print_message(parse_response(resp)?)
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let resp = self
+            .client
+            .delete(format!("{}/jobs/disabled", self.base_url))
+            .send()?;
+        self.print_message(parse_response(resp)?)
+    }
+
+    fn set_size(&self, job: &JobArgs, size: String) -> Result<(), Box<dyn Error>> {
+        let url = format!(
+            "{}/workers/{}/jobs/{}/size",
+            self.base_url, job.worker, job.mirror
+        );
+        let msg = json!({ "name": job.mirror, "size": size });
+        let resp = self.client.post(url).json(&msg).send()?;
+        let status: MirrorStatus = serde_json::from_value(parse_response(resp)?)?;
+        let jobs = vec![WebMirrorStatus::from(status)];
+        self.print(&jobs, || render_jobs(&jobs))
+    }
+
+    fn print<T: Serialize>(
+        &self,
+        value: &T,
+        table: impl FnOnce() -> String,
+    ) -> Result<(), Box<dyn Error>> {
+        match self.format {
+            OutputFormat::Table => print!("{}", table()),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        }
+        Ok(())
+    }
+
+    fn print_message(&self, body: Value) -> Result<(), Box<dyn Error>> {
+        let msg = body["message"].as_str().unwrap_or_default().to_string();
+        self.print(&body, || format!("{}\n", msg))
+    }
+}
+
+/// The JSON body of a manager response, or its `error` if the request failed.
+fn parse_response(resp: Response) -> Result<Value, Box<dyn Error>> {
+    let status = resp.status();
+    let body: Value = resp.json().unwrap_or(Value::Null);
+    if status.is_success() {
+        return Ok(body);
+    }
+    match body["error"].as_str() {
+        Some(err) => Err(format!("{} ({})", err, status).into()),
+        None => Err(format!("HTTP status code is not 200: {}", status).into()),
+    }
+}
+
+fn format_time(t: &DateTime<Utc>) -> String {
+    if t.timestamp() <= 0 {
+        return "-".to_string();
+    }
+    t.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S %z")
+        .to_string()
+}
+
+fn status_text<T: Serialize>(status: &T) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn render_jobs(jobs: &[WebMirrorStatus]) -> String {
+    let rows = jobs
+        .iter()
+        .map(|j| {
+            vec![
+                j.name.clone(),
+                status_text(&j.status),
+                format_time(&j.last_update_ts),
+                format_time(&j.next_scheduled_ts),
+                j.size.clone(),
+                j.upstream.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    render_table(
+        &[
+            "NAME",
+            "STATUS",
+            "LAST UPDATE",
+            "NEXT SCHEDULED",
+            "SIZE",
+            "UPSTREAM",
+        ],
+        &rows,
+    )
+}
+
+fn render_workers(workers: &[WorkerStatus]) -> String {
+    let rows = workers
+        .iter()
+        .map(|w| {
+            vec![
+                w.id.clone(),
+                w.url.clone(),
+                format_time(&w.last_online),
+                format_time(&w.last_register),
+            ]
+        })
+        .collect::<Vec<_>>();
+    render_table(&["ID", "URL", "LAST ONLINE", "LAST REGISTER"], &rows)
+}
+
+/// Lay out `rows` in left-aligned columns under `header`.
+fn render_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    std::iter::once(&header)
+        .chain(rows)
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+pub(crate) fn run_ctl(args: CtlArgs) -> Result<(), Box<dyn Error>> {
+    init_logger(args.verbose, args.debug, false);
+    let config = load_ctl_config(&args)?;
+    let ctl = Ctl::new(&config, args.format)?;
+    ctl.run(args.command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use hustsync_internal::status::SyncStatus;
+
+    #[test]
+    fn table_should_align_columns() {
+        let rows = vec![
+            vec!["elvish".to_string(), "success".to_string()],
+            vec!["archlinux-cn".to_string(), "-".to_string()],
+        ];
+        assert_eq!(
+            render_table(&["NAME", "STATUS"], &rows),
+            "NAME          STATUS\n\
+             elvish        success\n\
+             archlinux-cn  -\n"
+        );
+    }
+
+    #[test]
+    fn jobs_should_render_status_and_size() {
+        let t = Utc.with_ymd_and_hms(2016, 4, 16, 23, 8, 10).unwrap();
+        let status = MirrorStatus {
+            name: "elvish".into(),
+            worker: "test_worker".into(),
+            upstream: "rsync://rsync.elv.sh/elvish/".into(),
+            size: "1.33T".into(),
+            error_msg: String::new(),
+            last_update: t,
+            last_started: t,
+            last_ended: t,
+            next_schedule: DateTime::<Utc>::default(),
+            status: SyncStatus::PreSyncing,
+            is_master: true,
+        };
+        let table = render_jobs(&[WebMirrorStatus::from(status)]);
+        let row = table.lines().nth(1).unwrap();
+        assert!(row.starts_with("elvish  pre-syncing"));
+        assert!(row.contains(&format_time(&t)));
+        assert!(row.contains(" -  "));
+        assert!(row.ends_with("1.33T  rsync://rsync.elv.sh/elvish/"));
+    }
+}
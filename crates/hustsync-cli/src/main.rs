@@ -1,8 +1,16 @@
-use std::{error::Error, io, path::PathBuf};
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
+
+mod ctl;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use clap::{Args, Parser, ValueHint::FilePath};
+use ctl::CtlArgs;
+use hustsync_config_parser::{ManagerConfig, ManagerFileConfig, ManagerServerConfig, WorkerConfig};
 use hustsync_internal::logger::init_logger;
-use tracing::{info, warn};
+use tracing::info;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -24,6 +32,9 @@ enum Commands {
     /// Start the HustSync worker (aliased as 'w')
     #[command(alias = "w")]
     Worker(WorkerArgs),
+    /// Control the manager and its workers (aliased as 'c')
+    #[command(alias = "c")]
+    Ctl(CtlArgs),
 }
 
 #[derive(Args, Debug)]
@@ -58,6 +69,18 @@ struct ManagerArgs {
     /// The pid file of the manager process
     #[arg(long, default_value = "/run/hustsync/hustsync.manager.pid")]
     pidfile: PathBuf,
+    #[command(subcommand)]
+    command: Option<ManagerCommands>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ManagerCommands {
+    /// Copy workers and mirror statuses into the configured database
+    Import {
+        /// Read the BoltDB file `FILE` of a Go tunasync manager
+        #[arg(long, value_name = "FILE", value_hint = FilePath)]
+        from_bolt: PathBuf,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -78,24 +101,107 @@ struct WorkerArgs {
     pid_file: PathBuf,
 }
 
-// TODO
-fn start_manager(manager_args: ManagerArgs) -> () {
-    println!("Starting HustSync Manager...");
-    println!("Manager Args: {:?}", manager_args);
-    todo!()
+fn load_manager_config(manager_args: &ManagerArgs) -> Result<ManagerConfig, Box<dyn Error>> {
+    let mut config = match &manager_args.config {
+        Some(path) => hustsync_manager::load_config(path)?,
+        None => ManagerConfig::default(),
+    };
+
+    // command line flags take precedence over the config file
+    let server = config
+        .server
+        .get_or_insert_with(ManagerServerConfig::default);
+    if let Some(addr) = &manager_args.addr {
+        server.addr = Some(addr.clone());
+    }
+    if let Some(port) = manager_args.port {
+        server.port = Some(port);
+    }
+    if let Some(cert) = &manager_args.cert {
+        server.ssl_cert = Some(cert.to_string_lossy().into_owned());
+    }
+    if let Some(key) = &manager_args.key {
+        server.ssl_key = Some(key.to_string_lossy().into_owned());
+    }
+
+    let files = config.files.get_or_insert_with(ManagerFileConfig::default);
+    if let Some(db_file) = &manager_args.db_file {
+        files.db_file = Some(db_file.to_string_lossy().into_owned());
+    }
+    if let Some(db_type) = &manager_args.db_type {
+        files.db_type = Some(db_type.clone());
+    }
+
+    if manager_args.debug {
+        config.debug = Some(true);
+    }
+    Ok(config)
+}
+
+fn start_manager(manager_args: ManagerArgs) -> Result<(), Box<dyn Error>> {
+    let config = load_manager_config(&manager_args)?;
+    let debug = config.debug.unwrap_or(false);
+    init_logger(true, debug, manager_args.with_systemd);
+
+    info!("Starting HustSync Manager...");
+    let manager = hustsync_manager::get_hustsync_manager(config)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime
+        .block_on(manager.run())
+        .map_err(|e| e as Box<dyn Error>)
 }
 
-// TODO
-fn start_worker(worker_args: WorkerArgs) -> () {
-    println!("Starting HustSync Worker...");
-    todo!()
+fn import_to_manager(manager_args: &ManagerArgs, from_bolt: &Path) -> Result<(), Box<dyn Error>> {
+    let config = load_manager_config(manager_args)?;
+    let debug = config.debug.unwrap_or(false);
+    init_logger(true, debug, manager_args.with_systemd);
+
+    let manager = hustsync_manager::get_hustsync_manager(config)?;
+    let imported = manager.import_bolt(from_bolt)?;
+    info!(
+        "Imported {} workers and {} mirror statuses from {}",
+        imported.workers,
+        imported.mirror_statuses,
+        from_bolt.display()
+    );
+    Ok(())
+}
+
+fn start_worker(worker_args: WorkerArgs) -> Result<(), Box<dyn Error>> {
+    init_logger(
+        worker_args.verbose,
+        worker_args.debug,
+        worker_args.with_systemd,
+    );
+    let config = match &worker_args.config {
+        Some(path) => hustsync_worker::load_config(path)?,
+        None => WorkerConfig::default(),
+    };
+
+    info!("Starting HustSync Worker...");
+    let mut worker = hustsync_worker::get_hustsync_worker(config)?;
+    if let Some(path) = worker_args.config {
+        worker = worker.with_config_file(path);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime
+        .block_on(Arc::new(worker).run())
+        .map_err(|e| e as Box<dyn Error>)
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
     match cli.command {
-        Commands::Manager(m) => start_manager(m),
-        Commands::Worker(w) => start_worker(w),
+        Commands::Manager(m) => match &m.command {
+            Some(ManagerCommands::Import { from_bolt }) => import_to_manager(&m, from_bolt)?,
+            None => start_manager(m)?,
+        },
+        Commands::Worker(w) => start_worker(w)?,
+        Commands::Ctl(c) => ctl::run_ctl(c)?,
     }
 
     Ok(())
@@ -26,6 +26,9 @@ pub struct ManagerServerConfig {
     pub port: Option<u16>,
     pub ssl_cert: Option<String>,
     pub ssl_key: Option<String>,
+    /// Turn away worker calls without the worker token as bearer. Off by
+    /// default, since tunasync workers never send one.
+    pub require_worker_token: Option<bool>,
 }
 
 impl Default for ManagerServerConfig {
@@ -35,6 +38,7 @@ impl Default for ManagerServerConfig {
             port: Some(12345),
             ssl_cert: Some("".into()),
             ssl_key: Some("".into()),
+            require_worker_token: Some(false),
         }
     }
 }
@@ -124,6 +128,7 @@ pub struct WorkerManagerConfig {
     pub api_base: Option<String>,
     pub token: Option<String>,
     pub ca_cert: Option<String>,
+    pub token_file: Option<String>,
 }
 
 impl Default for WorkerManagerConfig {
@@ -132,6 +137,7 @@ impl Default for WorkerManagerConfig {
             api_base: Some("http://localhost:12345".into()),
             token: Some("".into()),
             ca_cert: Some("".into()),
+            token_file: Some("".into()),
         }
     }
 }
@@ -256,6 +262,24 @@ pub struct ExecOnStatusExtra {
     pub exec_on_failure_extra: Option<Vec<String>>,
 }
 
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CtlConfig {
+    pub manager_addr: Option<String>,
+    pub manager_port: Option<u16>,
+    pub ca_cert: Option<String>,
+}
+
+impl Default for CtlConfig {
+    fn default() -> Self {
+        CtlConfig {
+            manager_addr: Some("127.0.0.1".into()),
+            manager_port: Some(12345),
+            ca_cert: Some("".into()),
+        }
+    }
+}
+
 pub fn parse_config<T>(path: impl AsRef<Path>) -> Result<T, Box<dyn Error>>
 where
     T: DeserializeOwned,
@@ -1,6 +1,8 @@
+#![allow(clippy::unwrap_used, clippy::assertions_on_constants)]
+
 #[cfg(test)]
 mod tests {
-    use hustsync_config_parser::{ManagerConfig, WorkerConfig};
+    use hustsync_config_parser::{CtlConfig, ManagerConfig, WorkerConfig};
     use std::path::PathBuf;
 
     #[test]
@@ -29,6 +31,17 @@ mod tests {
         assert_eq!(worker_config, default_worker_config);
     }
 
+    #[test]
+    fn test_parse_ctl_config_with_defaults() {
+        let mut ctl_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        ctl_path.pop();
+        ctl_path.pop();
+        ctl_path.push("docs/example/ctl.conf");
+
+        let ctl_config: CtlConfig = hustsync_config_parser::parse_config(&ctl_path).unwrap();
+        assert_eq!(ctl_config, CtlConfig::default());
+    }
+
     /// 测试华科镜像实际使用的配置文件能否被正确解析
     /// 预期解析所有字段成功，且不报错
     #[test]
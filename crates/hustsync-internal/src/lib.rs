@@ -1,5 +1,15 @@
+#![cfg_attr(
+    test,
+    allow(
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::needless_raw_string_hashes
+    )
+)]
+
 pub mod logger;
 pub mod msg;
 pub mod status;
 pub mod status_web;
+pub mod tls;
 pub mod util;
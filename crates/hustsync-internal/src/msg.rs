@@ -7,8 +7,8 @@ use serde::Serialize;
 
 use crate::status::SyncStatus;
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub struct MirrorStatus {
     pub name: String,
     pub worker: String,
@@ -19,12 +19,12 @@ pub struct MirrorStatus {
     pub last_update: DateTime<Utc>,
     pub last_started: DateTime<Utc>,
     pub last_ended: DateTime<Utc>,
-    pub next_scheduled: DateTime<Utc>,
+    pub next_schedule: DateTime<Utc>,
     pub status: SyncStatus,
     pub is_master: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct WorkerStatus {
     pub id: String,
@@ -36,20 +36,20 @@ pub struct WorkerStatus {
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-struct MirrorSchedules {
-    schedules: Vec<MirrorSchedule>,
+pub struct MirrorSchedules {
+    pub schedules: Vec<MirrorSchedule>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-struct MirrorSchedule {
-    name: String,
-    next_schedule: DateTime<Utc>,
+pub struct MirrorSchedule {
+    pub name: String,
+    pub next_schedule: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-enum CmdVerb {
+#[serde(rename_all = "snake_case")]
+pub enum CmdVerb {
     Start,
     Stop,
     Disable,
@@ -60,19 +60,61 @@ enum CmdVerb {
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-struct WorkerCmd {
-    options: HashMap<String, bool>,
-    args: Vec<String>,
-    mirror_id: String,
-    cmd: CmdVerb,
+pub struct WorkerCmd {
+    pub options: HashMap<String, bool>,
+    pub args: Vec<String>,
+    pub mirror_id: String,
+    pub cmd: CmdVerb,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-struct ClientCmd {
-    options: HashMap<String, bool>,
-    args: Vec<String>,
-    mirror_id: String,
-    worker_id: String,
-    cmd: CmdVerb,
+pub struct ClientCmd {
+    pub options: HashMap<String, bool>,
+    pub args: Vec<String>,
+    pub mirror_id: String,
+    pub worker_id: String,
+    pub cmd: CmdVerb,
+}
+
+impl From<ClientCmd> for WorkerCmd {
+    fn from(cmd: ClientCmd) -> Self {
+        WorkerCmd {
+            options: cmd.options,
+            args: cmd.args,
+            mirror_id: cmd.mirror_id,
+            cmd: cmd.cmd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MirrorStatus;
+    use crate::status::SyncStatus;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn tunasync_mirror_status_should_round_trip() {
+        // as POSTed by a Go tunasync worker
+        let body = json!({
+            "name": "elvish",
+            "worker": "test_worker1",
+            "is_master": true,
+            "status": "success",
+            "last_update": "2024-05-01T10:00:00Z",
+            "last_started": "2024-05-01T09:59:00Z",
+            "last_ended": "2024-05-01T10:00:00Z",
+            "next_schedule": "0001-01-01T00:00:00Z",
+            "upstream": "rsync://rsync.elv.sh/elvish/",
+            "size": "1.33T",
+            "error_msg": "",
+        });
+        let status: MirrorStatus = serde_json::from_value(body.clone()).unwrap();
+        assert_eq!(status.status, SyncStatus::Success);
+        assert!(status.is_master);
+        assert_eq!(status.size, "1.33T");
+        let encoded: Value = serde_json::to_value(&status).unwrap();
+        assert_eq!(encoded, body);
+    }
 }
@@ -1,29 +1,71 @@
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::msg::MirrorStatus;
 use crate::status::SyncStatus;
 
-type TextTime = DateTime<chrono::Utc>;
-type StampTime = DateTime<chrono::Utc>;
-
+/// The status of a mirror as published to `status.json` and `/jobs`, laid out
+/// like tunasync's so existing mirror front pages can consume it.
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-struct WebMirrorStatus {
-    name: String,
-    upstream: String,
-    size: String,
-    laste_update: TextTime,
-    last_update_ts: StampTime,
-    last_started: TextTime,
-    last_started_ts: StampTime,
-    last_ended: TextTime,
-    last_ended_ts: StampTime,
-    next_scheduled: TextTime,
-    next_scheduled_ts: StampTime,
-    status: SyncStatus,
-    is_master: bool,
+#[serde(rename_all = "snake_case")]
+pub struct WebMirrorStatus {
+    pub name: String,
+    pub is_master: bool,
+    pub status: SyncStatus,
+    #[serde(with = "text_time")]
+    pub last_update: DateTime<Utc>,
+    #[serde(with = "stamp_time")]
+    pub last_update_ts: DateTime<Utc>,
+    #[serde(with = "text_time")]
+    pub last_started: DateTime<Utc>,
+    #[serde(with = "stamp_time")]
+    pub last_started_ts: DateTime<Utc>,
+    #[serde(with = "text_time")]
+    pub last_ended: DateTime<Utc>,
+    #[serde(with = "stamp_time")]
+    pub last_ended_ts: DateTime<Utc>,
+    #[serde(rename = "next_schedule", with = "text_time")]
+    pub next_scheduled: DateTime<Utc>,
+    #[serde(rename = "next_schedule_ts", with = "stamp_time")]
+    pub next_scheduled_ts: DateTime<Utc>,
+    pub upstream: String,
+    pub size: String,
+}
+
+/// Times formatted for humans, e.g. `2016-04-16 23:08:10 +0800`.
+mod text_time {
+    use chrono::{DateTime, Local, Utc};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+    pub(super) fn serialize<S: Serializer>(t: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(&t.with_timezone(&Local).format(FORMAT))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+        let text = String::deserialize(d)?;
+        DateTime::parse_from_str(&text, FORMAT)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Times as Unix timestamps in seconds.
+mod stamp_time {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub(super) fn serialize<S: Serializer>(t: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_i64(t.timestamp())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+        let ts = i64::deserialize(d)?;
+        DateTime::from_timestamp(ts, 0)
+            .ok_or_else(|| D::Error::custom(format!("timestamp {} out of range", ts)))
+    }
 }
 
 impl From<MirrorStatus> for WebMirrorStatus {
@@ -32,14 +74,14 @@ impl From<MirrorStatus> for WebMirrorStatus {
             name: ms.name,
             upstream: ms.upstream,
             size: ms.size,
-            laste_update: ms.last_update,
+            last_update: ms.last_update,
             last_update_ts: ms.last_update,
             last_started: ms.last_started,
             last_started_ts: ms.last_started,
             last_ended: ms.last_ended,
             last_ended_ts: ms.last_ended,
-            next_scheduled: ms.next_scheduled,
-            next_scheduled_ts: ms.next_scheduled,
+            next_scheduled: ms.next_schedule,
+            next_scheduled_ts: ms.next_schedule,
             status: ms.status,
             is_master: ms.is_master,
         }
@@ -59,7 +101,7 @@ mod tests {
             name: "hustlinux".to_string(),
             upstream: "rsync://mirrors.hust.edu.cn/hustlinux/".to_string(),
             size: "5GB".to_string(),
-            laste_update: t,
+            last_update: t,
             last_update_ts: t,
             last_started: t,
             last_started_ts: t,
@@ -78,11 +120,11 @@ mod tests {
         assert_eq!(m2.upstream, m.upstream);
         assert_eq!(m2.size, m.size);
 
-        assert_eq!(m2.laste_update.timestamp(), m.laste_update.timestamp());
+        assert_eq!(m2.last_update.timestamp(), m.last_update.timestamp());
         assert_eq!(m2.last_update_ts.timestamp(), m.last_update_ts.timestamp());
         assert_eq!(
-            m2.laste_update.timestamp_nanos_opt(),
-            m.laste_update.timestamp_nanos_opt()
+            m2.last_update.timestamp_nanos_opt(),
+            m.last_update.timestamp_nanos_opt()
         );
         assert_eq!(
             m2.last_update_ts.timestamp_nanos_opt(),
@@ -132,6 +174,30 @@ mod tests {
         assert_eq!(m2.status, m.status);
     }
 
+    #[test]
+    fn status_json_should_be_tunasync_compatible() {
+        let t = Utc.with_ymd_and_hms(2016, 4, 16, 23, 8, 10).unwrap();
+        let m = WebMirrorStatus::from(MirrorStatus {
+            name: "hustlinux".to_string(),
+            last_update: t,
+            next_schedule: t + Duration::minutes(5),
+            status: SyncStatus::Syncing,
+            ..MirrorStatus::default()
+        });
+
+        let v = serde_json::to_value(&m).expect("serialize should succeed");
+        assert_eq!(v["name"], "hustlinux");
+        assert_eq!(v["status"], "syncing");
+        assert_eq!(v["last_update_ts"], 1460848090);
+        assert_eq!(v["next_schedule_ts"], 1460848390);
+        let local = t.with_timezone(&chrono::Local);
+        assert_eq!(
+            v["last_update"],
+            local.format("%Y-%m-%d %H:%M:%S %z").to_string()
+        );
+        assert!(v.get("laste_update").is_none());
+    }
+
     #[test]
     fn build_web_mirror_status_should_work() {
         let now = Utc::now();
@@ -143,7 +209,7 @@ mod tests {
             last_update: now - Duration::minutes(30),
             last_started: now - Duration::minutes(1),
             last_ended: now,
-            next_scheduled: now + Duration::minutes(5),
+            next_schedule: now + Duration::minutes(5),
             upstream: "mirrors.tuna.tsinghua.edu.cn".to_string(),
             size: "4GB".to_string(),
             error_msg: "Network error".to_string(),
@@ -156,7 +222,7 @@ mod tests {
         assert_eq!(m2.size, "4GB");
         assert_eq!(m2.status, SyncStatus::Failed);
 
-        let lu = m2.laste_update.timestamp();
+        let lu = m2.last_update.timestamp();
         let lu_ts = m2.last_update_ts.timestamp();
         assert_eq!(lu, lu_ts);
         let ls = m2.last_started.timestamp();
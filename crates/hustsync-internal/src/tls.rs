@@ -0,0 +1,306 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tracing::{debug, error};
+
+// a client that stalls the handshake longer than this is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// handshaken connections waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 64;
+
+/// The TLS setup of a server, read from a certificate and key pair that can be
+/// read again while serving.
+#[derive(Debug)]
+pub struct TlsConfig {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    client_ca: Option<PathBuf>,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsConfig {
+    /// The TLS setup for the `ssl_cert`/`ssl_key` options of a server, or `None`
+    /// to serve plain HTTP when neither is set.
+    ///
+    /// Setting only one of them is an error rather than a silent fallback to HTTP.
+    pub fn from_pair(cert: Option<&str>, key: Option<&str>) -> io::Result<Option<Self>> {
+        let cert = cert.filter(|c| !c.is_empty());
+        let key = key.filter(|k| !k.is_empty());
+        match (cert, key) {
+            (None, None) => Ok(None),
+            (Some(cert), Some(key)) => Self::load(cert, key).map(Some),
+            (Some(_), None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ssl_cert is set but ssl_key is not, both are needed to serve HTTPS",
+            )),
+            (None, Some(_)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ssl_key is set but ssl_cert is not, both are needed to serve HTTPS",
+            )),
+        }
+    }
+
+    pub fn load(cert_file: impl Into<PathBuf>, key_file: impl Into<PathBuf>) -> io::Result<Self> {
+        let cert_file = cert_file.into();
+        let key_file = key_file.into();
+        let config = server_config(&cert_file, &key_file, None)?;
+        Ok(TlsConfig {
+            cert_file,
+            key_file,
+            client_ca: None,
+            current: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// Ask clients for a certificate and verify the ones they present against
+    /// the CA in `ca_file`.
+    ///
+    /// Clients may still connect without a certificate, see [`TlsPeer`].
+    pub fn with_client_ca(mut self, ca_file: impl Into<PathBuf>) -> io::Result<Self> {
+        let ca_file = ca_file.into();
+        let config = server_config(&self.cert_file, &self.key_file, Some(&ca_file))?;
+        self.client_ca = Some(ca_file);
+        self.current = RwLock::new(Arc::new(config));
+        Ok(self)
+    }
+
+    pub fn verifies_clients(&self) -> bool {
+        self.client_ca.is_some()
+    }
+
+    /// Read the certificate, key and client CA again, for the connections accepted
+    /// from now on.
+    ///
+    /// On error the previous certificate stays in use.
+    pub fn reload(&self) -> io::Result<()> {
+        let config = server_config(&self.cert_file, &self.key_file, self.client_ca.as_deref())?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+        Ok(())
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        let config = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        TlsAcceptor::from(Arc::clone(&config))
+    }
+}
+
+fn invalid_file(file: &Path, e: &dyn std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("failed to read {}: {}", file.display(), e),
+    )
+}
+
+fn read_certs(file: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_file(file, &e))?;
+    if certs.is_empty() {
+        return Err(invalid_file(file, &"no certificate found"));
+    }
+    Ok(certs)
+}
+
+fn server_config(
+    cert_file: &Path,
+    key_file: &Path,
+    client_ca: Option<&Path>,
+) -> io::Result<ServerConfig> {
+    let certs = read_certs(cert_file)?;
+    let key = PrivateKeyDer::from_pem_file(key_file).map_err(|e| invalid_file(key_file, &e))?;
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let builder = match client_ca {
+        None => builder.with_no_client_auth(),
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_file)? {
+                roots.add(cert).map_err(|e| invalid_file(ca_file, &e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .map_err(|e| invalid_file(ca_file, &e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    builder
+        .with_single_cert(certs, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Accepts TLS connections for `axum::serve`.
+///
+/// Handshakes run in their own tasks so a slow client cannot hold up the others.
+pub struct TlsListener {
+    conns: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+    accept_task: JoinHandle<()>,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, config: Arc<TlsConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (conn_tx, conns) = mpsc::channel(ACCEPT_BACKLOG);
+        let accept_task = tokio::spawn(accept_loop(listener, config, conn_tx));
+        Ok(TlsListener {
+            conns,
+            local_addr,
+            accept_task,
+        })
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.conns.recv().await {
+            Some(conn) => conn,
+            // the accept loop only stops with the listener itself
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Connection info for `into_make_service_with_connect_info`, telling whether
+/// the client presented a certificate signed by the client CA.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsPeer {
+    pub remote_addr: SocketAddr,
+    pub client_verified: bool,
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for TlsPeer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        // rustls only keeps the certificates of a client that passed verification
+        let (_, conn) = stream.io().get_ref();
+        TlsPeer {
+            remote_addr: *stream.remote_addr(),
+            client_verified: conn.peer_certificates().is_some_and(|c| !c.is_empty()),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for TlsPeer {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        TlsPeer {
+            remote_addr: *stream.remote_addr(),
+            client_verified: false,
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    config: Arc<TlsConfig>,
+    conn_tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                handle_accept_error(e).await;
+                continue;
+            }
+        };
+        let acceptor = config.acceptor();
+        let conn_tx = conn_tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = conn_tx.send((stream, addr)).await;
+                }
+                Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", addr, e),
+                Err(_) => debug!("TLS handshake with {} timed out", addr),
+            }
+        });
+    }
+}
+
+// same policy as the plain listener of axum
+async fn handle_accept_error(e: io::Error) {
+    if matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    ) {
+        return;
+    }
+    error!("accept error: {}", e);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_self_signed(dir: &Path) -> (PathBuf, PathBuf) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_file = dir.join("cert.pem");
+        let key_file = dir.join("key.pem");
+        fs::write(&cert_file, certified.cert.pem()).unwrap();
+        fs::write(&key_file, certified.signing_key.serialize_pem()).unwrap();
+        (cert_file, key_file)
+    }
+
+    #[test]
+    fn half_a_pair_should_be_rejected() {
+        assert!(TlsConfig::from_pair(None, None).unwrap().is_none());
+        assert!(TlsConfig::from_pair(Some(""), Some("")).unwrap().is_none());
+
+        let err = TlsConfig::from_pair(Some("cert.pem"), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = TlsConfig::from_pair(Some(""), Some("key.pem")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn bad_reload_should_keep_the_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_file, key_file) = write_self_signed(dir.path());
+        let config = TlsConfig::from_pair(cert_file.to_str(), key_file.to_str())
+            .unwrap()
+            .unwrap();
+        let before = Arc::clone(&config.current.read().unwrap());
+
+        fs::write(&key_file, "not a key").unwrap();
+        assert!(config.reload().is_err());
+        assert!(Arc::ptr_eq(&before, &config.current.read().unwrap()));
+
+        write_self_signed(dir.path());
+        config.reload().unwrap();
+        assert!(!Arc::ptr_eq(&before, &config.current.read().unwrap()));
+    }
+}
@@ -1,7 +1,6 @@
 use std::error::Error;
 use std::path::Path;
 
-use hustsync_config_parser;
 use hustsync_config_parser::ManagerConfig;
 
 pub fn load_config(cfg_file: impl AsRef<Path>) -> Result<ManagerConfig, Box<dyn Error>> {
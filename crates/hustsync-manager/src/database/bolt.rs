@@ -0,0 +1,309 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use hustsync_internal::msg::{MirrorStatus, WorkerStatus};
+use hustsync_internal::status::SyncStatus;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{AdapterError, DbAdapterTrait, STATUS_BUCKETKEY, WORKER_BUCKETKEY, decode};
+
+// The on-disk layout of bbolt, as the Go tunasync manager writes it: pages
+// of a fixed size, the first two holding alternate copies of the meta page.
+const MAGIC: u32 = 0xED0C_DAED;
+const FORMAT_VERSION: u32 = 2;
+const PAGE_HEADER_SIZE: usize = 16;
+// leaf and branch page elements have the same size
+const ELEMENT_SIZE: usize = 16;
+const BUCKET_HEADER_SIZE: usize = 16;
+const META_CHECKSUM_OFFSET: usize = 56;
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const META_PAGE: u16 = 0x04;
+const BUCKET_LEAF: u32 = 0x01;
+// used when the first meta page is too damaged to give the page size
+const DEFAULT_PAGE_SIZE: usize = 4096;
+// deeper than any real B+tree, so that a page cycle ends in an error
+const MAX_DEPTH: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum BoltError {
+    #[error("failed to read bolt file: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid bolt file: {0}")]
+    Invalid(String),
+    #[error("no bucket {0} in bolt file")]
+    MissingBucket(String),
+    #[error(transparent)]
+    Adapter(#[from] AdapterError),
+}
+
+/// What [`import_bolt`] copied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BoltImport {
+    pub workers: usize,
+    pub mirror_statuses: usize,
+}
+
+// field names and defaults as the Go manager marshals its records,
+// older versions of which lack some of the timestamps
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct GoWorkerStatus {
+    id: String,
+    url: String,
+    token: String,
+    last_online: DateTime<Utc>,
+    last_register: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct GoMirrorStatus {
+    name: String,
+    worker: String,
+    is_master: bool,
+    status: SyncStatus,
+    last_update: DateTime<Utc>,
+    last_started: DateTime<Utc>,
+    last_ended: DateTime<Utc>,
+    next_schedule: DateTime<Utc>,
+    upstream: String,
+    size: String,
+    error_msg: String,
+}
+
+impl From<GoWorkerStatus> for WorkerStatus {
+    fn from(w: GoWorkerStatus) -> Self {
+        WorkerStatus {
+            id: w.id,
+            url: w.url,
+            token: w.token,
+            last_online: w.last_online,
+            last_register: w.last_register,
+        }
+    }
+}
+
+impl From<GoMirrorStatus> for MirrorStatus {
+    fn from(m: GoMirrorStatus) -> Self {
+        MirrorStatus {
+            name: m.name,
+            worker: m.worker,
+            upstream: m.upstream,
+            size: m.size,
+            error_msg: m.error_msg,
+            last_update: m.last_update,
+            last_started: m.last_started,
+            last_ended: m.last_ended,
+            next_schedule: m.next_schedule,
+            status: m.status,
+            is_master: m.is_master,
+        }
+    }
+}
+
+/// Copy the workers and mirror statuses of the BoltDB file of a Go tunasync
+/// manager into `db`, replacing the records with the same keys. Either all of
+/// the records are written or none are, and importing again is harmless.
+pub fn import_bolt(path: &Path, db: &dyn DbAdapterTrait) -> Result<BoltImport, BoltError> {
+    let bolt = BoltFile::open(path)?;
+    let workers: Vec<WorkerStatus> = bolt
+        .bucket(WORKER_BUCKETKEY)?
+        .into_iter()
+        .map(|(k, v)| decode::<GoWorkerStatus>(&k, v).map(WorkerStatus::from))
+        .collect::<Result<_, _>>()?;
+    let statuses: Vec<MirrorStatus> = bolt
+        .bucket(STATUS_BUCKETKEY)?
+        .into_iter()
+        .map(|(k, v)| decode::<GoMirrorStatus>(&k, v).map(MirrorStatus::from))
+        .collect::<Result<_, _>>()?;
+
+    db.import(&workers, &statuses)?;
+    Ok(BoltImport {
+        workers: workers.len(),
+        mirror_statuses: statuses.len(),
+    })
+}
+
+fn invalid(msg: impl Into<String>) -> BoltError {
+    BoltError::Invalid(msg.into())
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], BoltError> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| invalid(format!("truncated at offset {}", offset)))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, BoltError> {
+    read_bytes(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, BoltError> {
+    read_bytes(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, BoltError> {
+    read_bytes(data, offset).map(u64::from_le_bytes)
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], BoltError> {
+    offset
+        .checked_add(len)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| invalid(format!("truncated at offset {}", offset)))
+}
+
+// 64-bit FNV-1a, which checksums the meta pages
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct Meta {
+    page_size: usize,
+    root: u64,
+    txid: u64,
+}
+
+fn read_meta(data: &[u8], offset: usize) -> Result<Meta, BoltError> {
+    if read_u16(data, offset + 8)? & META_PAGE == 0 {
+        return Err(invalid("no meta page"));
+    }
+    let meta = offset + PAGE_HEADER_SIZE;
+    if read_u32(data, meta)? != MAGIC {
+        return Err(invalid("not a bolt file"));
+    }
+    let version = read_u32(data, meta + 4)?;
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!("unsupported format version {}", version)));
+    }
+    let checksum = read_u64(data, meta + META_CHECKSUM_OFFSET)?;
+    if fnv1a(slice(data, meta, META_CHECKSUM_OFFSET)?) != checksum {
+        return Err(invalid("meta page checksum mismatch"));
+    }
+    Ok(Meta {
+        page_size: read_u32(data, meta + 8)? as usize,
+        root: read_u64(data, meta + 16)?,
+        txid: read_u64(data, meta + 48)?,
+    })
+}
+
+struct BoltFile {
+    data: Vec<u8>,
+    page_size: usize,
+    root: u64,
+}
+
+impl BoltFile {
+    fn open(path: &Path) -> Result<Self, BoltError> {
+        let data = fs::read(path)?;
+        if data.len() < PAGE_HEADER_SIZE + META_CHECKSUM_OFFSET + 8 {
+            return Err(invalid("not a bolt file"));
+        }
+        let first = read_meta(&data, 0);
+        let page_size = match &first {
+            Ok(meta) if meta.page_size >= PAGE_HEADER_SIZE => meta.page_size,
+            _ => DEFAULT_PAGE_SIZE,
+        };
+        // the meta page of the last committed transaction, unless it is torn
+        let meta = match (first, read_meta(&data, page_size)) {
+            (Ok(a), Ok(b)) => {
+                if a.txid >= b.txid {
+                    a
+                } else {
+                    b
+                }
+            }
+            (Ok(meta), Err(_)) | (Err(_), Ok(meta)) => meta,
+            (Err(e), Err(_)) => return Err(e),
+        };
+        Ok(BoltFile {
+            data,
+            page_size,
+            root: meta.root,
+        })
+    }
+
+    fn page(&self, id: u64) -> Result<&[u8], BoltError> {
+        let offset = usize::try_from(id)
+            .ok()
+            .and_then(|id| id.checked_mul(self.page_size))
+            .ok_or_else(|| invalid(format!("page {} out of range", id)))?;
+        let overflow = read_u32(&self.data, offset + 12)? as usize;
+        let len = overflow
+            .checked_add(1)
+            .and_then(|n| n.checked_mul(self.page_size))
+            .ok_or_else(|| invalid(format!("page {} out of range", id)))?;
+        slice(&self.data, offset, len)
+    }
+
+    // the root page of a bucket, stored after its header when inline
+    fn bucket_root<'a>(&'a self, header: &'a [u8]) -> Result<&'a [u8], BoltError> {
+        match read_u64(header, 0)? {
+            0 => header
+                .get(BUCKET_HEADER_SIZE..)
+                .ok_or_else(|| invalid("truncated inline bucket")),
+            root => self.page(root),
+        }
+    }
+
+    // the key, value and bucket flag of every element under `page`, in key order
+    fn collect<'a>(
+        &'a self,
+        page: &'a [u8],
+        depth: usize,
+        out: &mut Vec<(&'a [u8], &'a [u8], bool)>,
+    ) -> Result<(), BoltError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("page tree too deep"));
+        }
+        let flags = read_u16(page, 8)?;
+        let count = read_u16(page, 10)? as usize;
+        for i in 0..count {
+            let elem = PAGE_HEADER_SIZE + i * ELEMENT_SIZE;
+            if flags & LEAF_PAGE != 0 {
+                let elem_flags = read_u32(page, elem)?;
+                let pos = elem + read_u32(page, elem + 4)? as usize;
+                let ksize = read_u32(page, elem + 8)? as usize;
+                let vsize = read_u32(page, elem + 12)? as usize;
+                out.push((
+                    slice(page, pos, ksize)?,
+                    slice(page, pos + ksize, vsize)?,
+                    elem_flags & BUCKET_LEAF != 0,
+                ));
+            } else if flags & BRANCH_PAGE != 0 {
+                let child = read_u64(page, elem + 8)?;
+                self.collect(self.page(child)?, depth + 1, out)?;
+            } else {
+                return Err(invalid(format!("unexpected page flags {:#x}", flags)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The records of the top-level bucket `name`, leaving out nested buckets.
+    fn bucket(&self, name: &str) -> Result<Vec<(String, &[u8])>, BoltError> {
+        let mut top = Vec::new();
+        self.collect(self.page(self.root)?, 0, &mut top)?;
+        let Some(&(_, header, _)) = top
+            .iter()
+            .find(|&&(key, _, is_bucket)| is_bucket && key == name.as_bytes())
+        else {
+            return Err(BoltError::MissingBucket(name.to_string()));
+        };
+        let mut entries = Vec::new();
+        self.collect(self.bucket_root(header)?, 0, &mut entries)?;
+        Ok(entries
+            .into_iter()
+            .filter(|&(_, _, is_bucket)| !is_bucket)
+            .map(|(k, v, _)| (String::from_utf8_lossy(k).into_owned(), v))
+            .collect())
+    }
+}
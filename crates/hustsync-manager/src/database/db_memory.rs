@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::{PoisonError, RwLock};
+
+use super::AdapterError;
+use super::KvAdapterTrait;
+use super::KvOp;
+use super::KvPlan;
+use super::KvRead;
+
+// ordered, for prefix scans
+type Bucket = BTreeMap<String, Vec<u8>>;
+
+/// Buckets kept in memory only, lost when the manager stops.
+#[derive(Default)]
+pub(super) struct MemoryAdapter {
+    buckets: RwLock<HashMap<String, Bucket>>,
+}
+
+fn no_bucket(bucket: &str) -> AdapterError {
+    AdapterError::Internal(format!("bucket {} does not exist", bucket))
+}
+
+// reads of the buckets under whichever lock the caller holds
+struct Buckets<'a>(&'a HashMap<String, Bucket>);
+
+impl Buckets<'_> {
+    fn bucket(&self, bucket: &str) -> Result<&Bucket, AdapterError> {
+        self.0.get(bucket).ok_or_else(|| no_bucket(bucket))
+    }
+}
+
+impl KvRead for Buckets<'_> {
+    fn get(&mut self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError> {
+        Ok(self.bucket(bucket)?.get(key).cloned())
+    }
+
+    fn get_all(&mut self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
+        let table = self.bucket(bucket)?;
+        Ok(table.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+    }
+
+    fn scan_prefix(
+        &mut self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+        Ok(self
+            .bucket(bucket)?
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+}
+
+fn apply_batch(
+    buckets: &mut HashMap<String, Bucket>,
+    ops: &[KvOp<'_>],
+) -> Result<(), AdapterError> {
+    // the lock keeps the batch whole for readers, so only a missing bucket
+    // can fail it, and that is checked before anything is written
+    for op in ops {
+        let (KvOp::Put { bucket, .. } | KvOp::Delete { bucket, .. }) = op;
+        if !buckets.contains_key(*bucket) {
+            return Err(no_bucket(bucket));
+        }
+    }
+    for op in ops {
+        match op {
+            KvOp::Put { bucket, key, value } => {
+                if let Some(table) = buckets.get_mut(*bucket) {
+                    table.insert(key.clone(), value.clone());
+                }
+            }
+            KvOp::Delete { bucket, key } => {
+                if let Some(table) = buckets.get_mut(*bucket) {
+                    table.remove(key);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl KvAdapterTrait for MemoryAdapter {
+    fn init_bucket(&self, bucket: &str) -> Result<(), AdapterError> {
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        buckets.entry(bucket.to_string()).or_default();
+        Ok(())
+    }
+
+    fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError> {
+        let buckets = self.buckets.read().unwrap_or_else(PoisonError::into_inner);
+        Buckets(&buckets).get(bucket, key)
+    }
+
+    fn get_all(&self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
+        let buckets = self.buckets.read().unwrap_or_else(PoisonError::into_inner);
+        Buckets(&buckets).get_all(bucket)
+    }
+
+    fn scan_prefix(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+        let buckets = self.buckets.read().unwrap_or_else(PoisonError::into_inner);
+        Buckets(&buckets).scan_prefix(bucket, prefix)
+    }
+
+    fn put(&self, bucket: &str, key: &str, value: &[u8]) -> Result<(), AdapterError> {
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        let table = buckets.get_mut(bucket).ok_or_else(|| no_bucket(bucket))?;
+        table.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn write_batch(&self, ops: &[KvOp<'_>]) -> Result<(), AdapterError> {
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        apply_batch(&mut buckets, ops)
+    }
+
+    fn update(&self, _watched: &[&str], plan: &mut KvPlan<'_>) -> Result<(), AdapterError> {
+        // nothing else is written while the plan runs under the lock
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        let ops = plan(&mut Buckets(&buckets))?;
+        apply_batch(&mut buckets, &ops)
+    }
+
+    fn close(&self) -> Result<(), AdapterError> {
+        Ok(())
+    }
+}
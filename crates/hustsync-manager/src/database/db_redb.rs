@@ -1,15 +1,99 @@
 use std::collections::HashMap;
 
 use redb;
-use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition, WriteTransaction};
 
 use super::AdapterError;
 use super::KvAdapterTrait;
+use super::KvOp;
+use super::KvPlan;
+use super::KvRead;
 
 pub(super) struct RedbAdapter {
     pub(super) db: Database,
 }
 
+fn table_get(
+    table: &impl ReadableTable<&'static str, &'static [u8]>,
+    key: &str,
+) -> Result<Option<Vec<u8>>, AdapterError> {
+    match table.get(key)? {
+        Some(value) => Ok(Some(value.value().to_vec())),
+        None => Ok(None),
+    }
+}
+
+fn table_get_all(
+    table: &impl ReadableTable<&'static str, &'static [u8]>,
+) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
+    let mut map = HashMap::new();
+    for item in table.iter()? {
+        let (key_guard, value_guard) = item?;
+        let key = key_guard.value().to_string();
+        let value = value_guard.value().to_vec();
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
+fn table_scan_prefix(
+    table: &impl ReadableTable<&'static str, &'static [u8]>,
+    prefix: &str,
+) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+    let mut entries = Vec::new();
+    for item in table.range(prefix..)? {
+        let (key_guard, value_guard) = item?;
+        let key = key_guard.value();
+        if !key.starts_with(prefix) {
+            break;
+        }
+        entries.push((key.to_string(), value_guard.value().to_vec()));
+    }
+    Ok(entries)
+}
+
+fn apply_batch(write_txn: &WriteTransaction, ops: &[KvOp<'_>]) -> Result<(), AdapterError> {
+    for op in ops {
+        match op {
+            KvOp::Put { bucket, key, value } => {
+                let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+                let mut table = write_txn.open_table(table_def)?;
+                table.insert(key.as_str(), value.as_slice())?;
+            }
+            KvOp::Delete { bucket, key } => {
+                let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+                let mut table = write_txn.open_table(table_def)?;
+                table.remove(key.as_str())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// reads within the write transaction of an update
+struct WriteTxnReader<'a>(&'a WriteTransaction);
+
+impl KvRead for WriteTxnReader<'_> {
+    fn get(&mut self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError> {
+        let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+        table_get(&self.0.open_table(table_def)?, key)
+    }
+
+    fn get_all(&mut self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
+        let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+        table_get_all(&self.0.open_table(table_def)?)
+    }
+
+    fn scan_prefix(
+        &mut self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+        let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+        table_scan_prefix(&self.0.open_table(table_def)?, prefix)
+    }
+}
+
 impl KvAdapterTrait for RedbAdapter {
     fn init_bucket(&self, bucket: &str) -> Result<(), AdapterError> {
         let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
@@ -22,25 +106,23 @@ impl KvAdapterTrait for RedbAdapter {
     fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError> {
         let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
         let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(table_def)?;
-        match table.get(key)? {
-            Some(value) => Ok(Some(value.value().to_vec())),
-            None => Ok(None),
-        }
+        table_get(&read_txn.open_table(table_def)?, key)
     }
 
     fn get_all(&self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
         let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
         let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(table_def)?;
-        let mut map = HashMap::new();
-        for item in table.iter()? {
-            let (key_guard, value_guard) = item?;
-            let key = key_guard.value().to_string();
-            let value = value_guard.value().to_vec();
-            map.insert(key, value);
-        }
-        Ok(map)
+        table_get_all(&read_txn.open_table(table_def)?)
+    }
+
+    fn scan_prefix(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+        let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+        let read_txn = self.db.begin_read()?;
+        table_scan_prefix(&read_txn.open_table(table_def)?, prefix)
     }
 
     fn put(&self, bucket: &str, key: &str, value: &[u8]) -> Result<(), AdapterError> {
@@ -54,13 +136,20 @@ impl KvAdapterTrait for RedbAdapter {
         Ok(())
     }
 
-    fn delete(&self, bucket: &str, key: &str) -> Result<(), AdapterError> {
-        let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new(bucket);
+    fn write_batch(&self, ops: &[KvOp<'_>]) -> Result<(), AdapterError> {
+        // uncommitted writes are rolled back when the transaction is dropped
         let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(table_def)?;
-            table.remove(key)?;
-        }
+        apply_batch(&write_txn, ops)?;
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn update(&self, _watched: &[&str], plan: &mut KvPlan<'_>) -> Result<(), AdapterError> {
+        // redb has one writer at a time, so reading within the write
+        // transaction sees nothing change until the commit
+        let write_txn = self.db.begin_write()?;
+        let ops = plan(&mut WriteTxnReader(&write_txn))?;
+        apply_batch(&write_txn, &ops)?;
         write_txn.commit()?;
         Ok(())
     }
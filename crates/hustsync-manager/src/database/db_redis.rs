@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use redis::{Client, Cmd, Connection, FromRedisValue, Pipeline, RedisError};
+
+use super::AdapterError;
+use super::KvAdapterTrait;
+use super::KvOp;
+use super::KvPlan;
+use super::KvRead;
+
+// how long to wait for the server to accept a connection or answer a command,
+// before reporting it unavailable rather than stalling the request
+const REDIS_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The fields of each bucket are kept in order in a sorted set beside its hash,
+// all with the same score, so a prefix scan reads only the fields it returns
+// rather than walking the whole hash.
+fn keys_of(bucket: &str) -> String {
+    format!("{}:keys", bucket)
+}
+
+fn open_connection(client: &Client) -> Result<Connection, AdapterError> {
+    let conn = client
+        .get_connection_with_timeout(REDIS_TIMEOUT)
+        .map_err(redis_error)?;
+    conn.set_read_timeout(Some(REDIS_TIMEOUT))
+        .map_err(redis_error)?;
+    conn.set_write_timeout(Some(REDIS_TIMEOUT))
+        .map_err(redis_error)?;
+    Ok(conn)
+}
+
+// a server that cannot be reached is told apart from one that refuses a command
+fn redis_error(e: RedisError) -> AdapterError {
+    if e.is_io_error() || e.is_connection_refusal() || e.is_connection_dropped() || e.is_timeout() {
+        AdapterError::BackendUnavailable(e.to_string())
+    } else {
+        AdapterError::RedisError(e)
+    }
+}
+
+fn query<T: FromRedisValue>(conn: &mut Connection, cmd: &Cmd) -> Result<T, AdapterError> {
+    cmd.query(conn).map_err(redis_error)
+}
+
+fn scan_prefix(
+    conn: &mut Connection,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+    let min = [b"[", prefix.as_bytes()].concat();
+    // no byte of a string key is 0xff, so this bounds all keys with the prefix
+    let max = [b"(", prefix.as_bytes(), b"\xff"].concat();
+    let keys: Vec<String> = query(
+        conn,
+        redis::cmd("ZRANGEBYLEX")
+            .arg(keys_of(bucket))
+            .arg(min)
+            .arg(max),
+    )?;
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    let values: Vec<Option<Vec<u8>>> = query(conn, redis::cmd("HMGET").arg(bucket).arg(&keys))?;
+    // a field deleted between the two reads is left out
+    Ok(keys
+        .into_iter()
+        .zip(values)
+        .filter_map(|(k, v)| Some((k, v?)))
+        .collect())
+}
+
+// sent as MULTI/EXEC, which other clients never see half applied
+fn batch_pipe(ops: &[KvOp<'_>]) -> Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for op in ops {
+        match op {
+            KvOp::Put { bucket, key, value } => {
+                pipe.hset(*bucket, key, value.as_slice()).ignore();
+                pipe.zadd(keys_of(bucket), key, 0).ignore();
+            }
+            KvOp::Delete { bucket, key } => {
+                pipe.hdel(*bucket, key).ignore();
+                pipe.zrem(keys_of(bucket), key).ignore();
+            }
+        }
+    }
+    pipe
+}
+
+// index the fields of a hash written before its sorted set was kept
+fn index_keys(conn: &mut Connection, bucket: &str) -> Result<(), AdapterError> {
+    let sorted = keys_of(bucket);
+    loop {
+        query::<()>(conn, redis::cmd("WATCH").arg(bucket).arg(&sorted))?;
+        let indexed: bool = query(conn, redis::cmd("EXISTS").arg(&sorted))?;
+        let keys: Vec<String> = if indexed {
+            Vec::new()
+        } else {
+            query(conn, redis::cmd("HKEYS").arg(bucket))?
+        };
+        if keys.is_empty() {
+            return query(conn, &redis::cmd("UNWATCH"));
+        }
+        let members: Vec<(u8, &str)> = keys.iter().map(|k| (0, k.as_str())).collect();
+        let applied: Option<()> = redis::pipe()
+            .atomic()
+            .zadd_multiple(&sorted, &members)
+            .ignore()
+            .query(conn)
+            .map_err(redis_error)?;
+        if applied.is_some() {
+            return Ok(());
+        }
+    }
+}
+
+// reads on the connection that watches the buckets of an update
+struct ConnReader<'a>(&'a mut Connection);
+
+impl KvRead for ConnReader<'_> {
+    fn get(&mut self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError> {
+        query(self.0, redis::cmd("HGET").arg(bucket).arg(key))
+    }
+
+    fn get_all(&mut self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
+        query(self.0, redis::cmd("HGETALL").arg(bucket))
+    }
+
+    fn scan_prefix(
+        &mut self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+        scan_prefix(self.0, bucket, prefix)
+    }
+}
+
+/// Buckets stored as Redis hashes, so several managers can share one state.
+pub(super) struct RedisAdapter {
+    client: Client,
+    // dropped after a connection error, to connect again on the next command
+    conn: Mutex<Option<Connection>>,
+}
+
+impl RedisAdapter {
+    /// Connect to the server at `url`, such as `redis://127.0.0.1:6379/0`.
+    pub(super) fn connect(url: &str) -> Result<Self, AdapterError> {
+        let client = Client::open(url)?;
+        let conn = open_connection(&client)?;
+        Ok(RedisAdapter {
+            client,
+            conn: Mutex::new(Some(conn)),
+        })
+    }
+
+    fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, AdapterError>,
+    ) -> Result<T, AdapterError> {
+        let mut guard = self.conn.lock().unwrap_or_else(PoisonError::into_inner);
+        let conn = match guard.as_mut() {
+            Some(conn) => conn,
+            None => guard.insert(open_connection(&self.client)?),
+        };
+        let result = f(conn);
+        if let Err(AdapterError::BackendUnavailable(_)) = &result {
+            *guard = None;
+        }
+        result
+    }
+
+    fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> Result<T, AdapterError> {
+        self.with_conn(|conn| query(conn, cmd))
+    }
+}
+
+impl KvAdapterTrait for RedisAdapter {
+    fn init_bucket(&self, bucket: &str) -> Result<(), AdapterError> {
+        // a hash springs into existence with its first field, but one left
+        // by an older version lacks the sorted set of its fields
+        self.with_conn(|conn| index_keys(conn, bucket))
+    }
+
+    fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError> {
+        self.query(redis::cmd("HGET").arg(bucket).arg(key))
+    }
+
+    fn get_all(&self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError> {
+        self.query(redis::cmd("HGETALL").arg(bucket))
+    }
+
+    fn scan_prefix(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError> {
+        self.with_conn(|conn| scan_prefix(conn, bucket, prefix))
+    }
+
+    fn put(&self, bucket: &str, key: &str, value: &[u8]) -> Result<(), AdapterError> {
+        self.write_batch(&[KvOp::Put {
+            bucket,
+            key: key.to_string(),
+            value: value.to_vec(),
+        }])
+    }
+
+    fn write_batch(&self, ops: &[KvOp<'_>]) -> Result<(), AdapterError> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        self.with_conn(|conn| batch_pipe(ops).query(conn).map_err(redis_error))
+    }
+
+    fn update(&self, watched: &[&str], plan: &mut KvPlan<'_>) -> Result<(), AdapterError> {
+        // other managers share the server, so the buckets are watched there
+        // rather than locked here
+        self.with_conn(|conn| {
+            loop {
+                query::<()>(conn, redis::cmd("WATCH").arg(watched))?;
+                let ops = match plan(&mut ConnReader(conn)) {
+                    Ok(ops) if !ops.is_empty() => ops,
+                    done => {
+                        query::<()>(conn, &redis::cmd("UNWATCH"))?;
+                        return done.map(drop);
+                    }
+                };
+                // EXEC answers nil when a watched bucket was written since WATCH
+                let applied: Option<()> = batch_pipe(&ops).query(conn).map_err(redis_error)?;
+                if applied.is_some() {
+                    return Ok(());
+                }
+            }
+        })
+    }
+
+    fn close(&self) -> Result<(), AdapterError> {
+        self.conn
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        Ok(())
+    }
+}
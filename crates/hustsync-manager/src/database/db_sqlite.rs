@@ -0,0 +1,315 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use hustsync_internal::msg::{MirrorStatus, WorkerStatus};
+use hustsync_internal::status::SyncStatus;
+use rusqlite::types::Type;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+
+use super::{AdapterError, DbAdapterTrait};
+
+// the primary key of mirror_status also serves lookups by worker_id alone
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS workers (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    token TEXT NOT NULL,
+    last_online TEXT NOT NULL,
+    last_register TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS mirror_status (
+    worker_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    upstream TEXT NOT NULL,
+    size TEXT NOT NULL,
+    error_msg TEXT NOT NULL,
+    last_update TEXT NOT NULL,
+    last_started TEXT NOT NULL,
+    last_ended TEXT NOT NULL,
+    next_scheduled TEXT NOT NULL,
+    status TEXT NOT NULL,
+    is_master INTEGER NOT NULL,
+    PRIMARY KEY (worker_id, name)
+);
+CREATE INDEX IF NOT EXISTS mirror_status_name ON mirror_status (name);
+CREATE INDEX IF NOT EXISTS mirror_status_status ON mirror_status (status);
+CREATE TABLE IF NOT EXISTS mirror_status_archive AS SELECT * FROM mirror_status WHERE 0;
+";
+
+// kept in `PRAGMA user_version`, 0 in files written before it was recorded
+const SCHEMA_VERSION: u32 = 1;
+
+const MIRROR_COLUMNS: &str = "worker_id, name, upstream, size, error_msg, last_update, \
+     last_started, last_ended, next_scheduled, status, is_master";
+
+/// Workers and mirror statuses kept in SQLite tables, one column per field.
+pub(super) struct SqliteAdapter {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteAdapter {
+    pub(super) fn open(path: &str) -> Result<Self, AdapterError> {
+        Ok(SqliteAdapter {
+            conn: Mutex::new(Connection::open(path)?),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// statuses are stored by their JSON names, such as "pre-syncing"
+fn status_to_sql(status: SyncStatus) -> Result<String, AdapterError> {
+    match serde_json::to_value(status) {
+        Ok(serde_json::Value::String(s)) => Ok(s),
+        Ok(v) => Err(AdapterError::Internal(format!("unexpected status {}", v))),
+        Err(e) => Err(AdapterError::Serialization(e)),
+    }
+}
+
+// a status this build does not know is a corrupt row, not an unknown status
+fn status_from_sql(row: &Row<'_>, idx: usize) -> rusqlite::Result<SyncStatus> {
+    let name: String = row.get(idx)?;
+    let status = serde_json::from_value(serde_json::Value::String(name.clone()))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e)))?;
+    // serde falls back to Unknown for any name it does not know
+    if status == SyncStatus::Unknown && name != "unknown" {
+        return Err(rusqlite::Error::FromSqlConversionFailure(
+            idx,
+            Type::Text,
+            format!("unknown status '{}'", name).into(),
+        ));
+    }
+    Ok(status)
+}
+
+fn worker_from_row(row: &Row<'_>) -> rusqlite::Result<WorkerStatus> {
+    Ok(WorkerStatus {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        token: row.get(2)?,
+        last_online: row.get(3)?,
+        last_register: row.get(4)?,
+    })
+}
+
+fn mirror_from_row(row: &Row<'_>) -> rusqlite::Result<MirrorStatus> {
+    Ok(MirrorStatus {
+        worker: row.get(0)?,
+        name: row.get(1)?,
+        upstream: row.get(2)?,
+        size: row.get(3)?,
+        error_msg: row.get(4)?,
+        last_update: row.get(5)?,
+        last_started: row.get(6)?,
+        last_ended: row.get(7)?,
+        next_schedule: row.get(8)?,
+        status: status_from_sql(row, 9)?,
+        is_master: row.get(10)?,
+    })
+}
+
+fn put_worker(conn: &Connection, w: &WorkerStatus) -> Result<(), AdapterError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO workers (id, url, token, last_online, last_register)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![w.id, w.url, w.token, w.last_online, w.last_register],
+    )?;
+    Ok(())
+}
+
+fn put_mirror_status(
+    conn: &Connection,
+    worker_id: &str,
+    mirror_id: &str,
+    status: &MirrorStatus,
+) -> Result<(), AdapterError> {
+    let sql = format!(
+        "INSERT OR REPLACE INTO mirror_status ({}) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        MIRROR_COLUMNS
+    );
+    conn.execute(
+        &sql,
+        params![
+            worker_id,
+            mirror_id,
+            status.upstream,
+            status.size,
+            status.error_msg,
+            status.last_update,
+            status.last_started,
+            status.last_ended,
+            status.next_schedule,
+            status_to_sql(status.status)?,
+            status.is_master,
+        ],
+    )?;
+    Ok(())
+}
+
+fn invalid_worker(worker_id: &str) -> AdapterError {
+    AdapterError::WorkerNotFound(worker_id.to_string())
+}
+
+impl DbAdapterTrait for SqliteAdapter {
+    fn init(&self) -> Result<(), AdapterError> {
+        let conn = self.conn();
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(AdapterError::SchemaTooNew {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(())
+    }
+
+    fn list_workers(&self) -> Result<Vec<WorkerStatus>, AdapterError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, url, token, last_online, last_register FROM workers ORDER BY id",
+        )?;
+        let workers = stmt
+            .query_map([], worker_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(workers)
+    }
+
+    fn get_worker(&self, worker_id: &str) -> Result<WorkerStatus, AdapterError> {
+        self.conn()
+            .query_row(
+                "SELECT id, url, token, last_online, last_register FROM workers WHERE id = ?1",
+                [worker_id],
+                worker_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| invalid_worker(worker_id))
+    }
+
+    fn delete_worker(&self, worker_id: &str, keep_history: bool) -> Result<(), AdapterError> {
+        let mut conn = self.conn();
+        // rolled back when dropped without commit
+        let tx = conn.transaction()?;
+        let deleted = tx.execute("DELETE FROM workers WHERE id = ?1", [worker_id])?;
+        if deleted == 0 {
+            return Err(invalid_worker(worker_id));
+        }
+        if keep_history {
+            let sql = format!(
+                "INSERT INTO mirror_status_archive ({0}) \
+                 SELECT {0} FROM mirror_status WHERE worker_id = ?1",
+                MIRROR_COLUMNS
+            );
+            tx.execute(&sql, [worker_id])?;
+        }
+        tx.execute(
+            "DELETE FROM mirror_status WHERE worker_id = ?1",
+            [worker_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn create_worker(&self, w: WorkerStatus) -> Result<WorkerStatus, AdapterError> {
+        put_worker(&self.conn(), &w)?;
+        Ok(w)
+    }
+
+    fn refresh_worker(&self, worker_id: &str) -> Result<WorkerStatus, AdapterError> {
+        let updated = self.conn().execute(
+            "UPDATE workers SET last_online = ?1 WHERE id = ?2",
+            params![chrono::Utc::now(), worker_id],
+        )?;
+        if updated == 0 {
+            return Err(invalid_worker(worker_id));
+        }
+        self.get_worker(worker_id)
+    }
+
+    fn update_mirror_status(
+        &self,
+        worker_id: &str,
+        mirror_id: &str,
+        status: MirrorStatus,
+    ) -> Result<MirrorStatus, AdapterError> {
+        put_mirror_status(&self.conn(), worker_id, mirror_id, &status)?;
+        Ok(status)
+    }
+
+    fn get_mirror_status(
+        &self,
+        worker_id: &str,
+        mirror_id: &str,
+    ) -> Result<MirrorStatus, AdapterError> {
+        let sql = format!(
+            "SELECT {} FROM mirror_status WHERE worker_id = ?1 AND name = ?2",
+            MIRROR_COLUMNS
+        );
+        self.conn()
+            .query_row(&sql, [worker_id, mirror_id], mirror_from_row)
+            .optional()?
+            .ok_or_else(|| AdapterError::MirrorNotFound {
+                worker: worker_id.to_string(),
+                mirror: mirror_id.to_string(),
+            })
+    }
+
+    fn list_mirror_status(&self, worker_id: &str) -> Result<Vec<MirrorStatus>, AdapterError> {
+        let sql = format!(
+            "SELECT {} FROM mirror_status WHERE worker_id = ?1 ORDER BY name",
+            MIRROR_COLUMNS
+        );
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&sql)?;
+        let statuses = stmt
+            .query_map([worker_id], mirror_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(statuses)
+    }
+
+    fn list_all_mirror_status(&self) -> Result<Vec<MirrorStatus>, AdapterError> {
+        let sql = format!(
+            "SELECT {} FROM mirror_status ORDER BY name, worker_id",
+            MIRROR_COLUMNS
+        );
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&sql)?;
+        let statuses = stmt
+            .query_map([], mirror_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(statuses)
+    }
+
+    fn flush_disabled_jobs(&self) -> Result<(), AdapterError> {
+        self.conn().execute(
+            "DELETE FROM mirror_status WHERE status = ?1 OR name = ''",
+            [status_to_sql(SyncStatus::Disabled)?],
+        )?;
+        Ok(())
+    }
+
+    fn import(
+        &self,
+        workers: &[WorkerStatus],
+        statuses: &[MirrorStatus],
+    ) -> Result<(), AdapterError> {
+        let mut conn = self.conn();
+        // rolled back when dropped without commit
+        let tx = conn.transaction()?;
+        for w in workers {
+            put_worker(&tx, w)?;
+        }
+        for m in statuses {
+            put_mirror_status(&tx, &m.worker, &m.name, m)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), AdapterError> {
+        Ok(())
+    }
+}
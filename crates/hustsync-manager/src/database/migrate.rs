@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::common::info_hustsync;
+
+use super::{
+    ARCHIVE_BUCKETKEY, AdapterError, KvAdapterTrait, KvOp, STATUS_BUCKETKEY,
+    WORKER_INDEX_BUCKETKEY, decode, encode, worker_index_key,
+};
+
+const META_BUCKETKEY: &str = "meta";
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The version of the records written by this build, that of the last migration.
+const SCHEMA_VERSION: u32 = 3;
+
+// databases written before the version was recorded
+const UNVERSIONED: u32 = 1;
+
+/// Upgrades the records from `version - 1` to `version`.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    /// The writes to make, applied in one batch with the new version.
+    migrate: fn(&dyn KvAdapterTrait) -> Result<Vec<KvOp<'static>>, AdapterError>,
+}
+
+// Migrations decode only the fields they need rather than the current
+// structs, so they keep working when `MirrorStatus` or `WorkerStatus` change.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        description: "index mirror statuses by worker",
+        migrate: index_statuses_by_worker,
+    },
+    Migration {
+        version: 3,
+        description: "name mirror status fields as tunasync does",
+        migrate: rename_status_fields,
+    },
+];
+
+/// Bring the records of `kv` up to [`SCHEMA_VERSION`], refusing databases
+/// written by a newer build.
+pub(super) fn migrate(kv: &dyn KvAdapterTrait) -> Result<(), AdapterError> {
+    kv.init_bucket(META_BUCKETKEY)?;
+    let version = match kv.get(META_BUCKETKEY, SCHEMA_VERSION_KEY)? {
+        Some(bytes) => decode(SCHEMA_VERSION_KEY, &bytes)?,
+        None => UNVERSIONED,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(AdapterError::SchemaTooNew {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for m in MIGRATIONS.iter().filter(|m| m.version > version) {
+        info_hustsync(&format!(
+            "Migrating database to schema version {}: {}",
+            m.version, m.description
+        ));
+        let mut ops = (m.migrate)(kv)?;
+        ops.push(KvOp::Put {
+            bucket: META_BUCKETKEY,
+            key: SCHEMA_VERSION_KEY.to_string(),
+            value: encode(&m.version)?,
+        });
+        // a crash leaves the records at the version they are stamped with
+        kv.write_batch(&ops)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct StatusOwner {
+    name: String,
+    worker: String,
+}
+
+fn index_statuses_by_worker(kv: &dyn KvAdapterTrait) -> Result<Vec<KvOp<'static>>, AdapterError> {
+    let mut ops = Vec::new();
+    for (k, v) in kv.get_all(STATUS_BUCKETKEY)? {
+        let owner: StatusOwner = decode(&k, &v)?;
+        ops.push(KvOp::Put {
+            bucket: WORKER_INDEX_BUCKETKEY,
+            key: worker_index_key(&owner.worker, &owner.name),
+            value: k.into_bytes(),
+        });
+    }
+    Ok(ops)
+}
+
+// the kebab-case names statuses were stored with, and their tunasync names
+const RENAMED_STATUS_FIELDS: &[(&str, &str)] = &[
+    ("error-msg", "error_msg"),
+    ("last-update", "last_update"),
+    ("last-started", "last_started"),
+    ("last-ended", "last_ended"),
+    ("next-scheduled", "next_schedule"),
+    ("is-master", "is_master"),
+];
+
+fn rename_status_fields(kv: &dyn KvAdapterTrait) -> Result<Vec<KvOp<'static>>, AdapterError> {
+    let mut ops = Vec::new();
+    for bucket in [STATUS_BUCKETKEY, ARCHIVE_BUCKETKEY] {
+        for (k, v) in kv.get_all(bucket)? {
+            let mut fields: Map<String, Value> = decode(&k, &v)?;
+            let mut renamed = false;
+            for (old, new) in RENAMED_STATUS_FIELDS {
+                if let Some(value) = fields.remove(*old) {
+                    fields.insert(new.to_string(), value);
+                    renamed = true;
+                }
+            }
+            if renamed {
+                ops.push(KvOp::Put {
+                    bucket,
+                    key: k,
+                    value: encode(&fields)?,
+                });
+            }
+        }
+    }
+    Ok(ops)
+}
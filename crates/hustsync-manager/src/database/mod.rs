@@ -1,21 +1,42 @@
-use std::{collections::HashMap, fmt, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
 
 use hustsync_internal::msg::{MirrorStatus, WorkerStatus};
+use serde::{Serialize, de::DeserializeOwned};
 use thiserror::Error;
 
+use crate::database::db_memory::MemoryAdapter;
 use crate::database::db_redb::RedbAdapter;
+use crate::database::db_redis::RedisAdapter;
+use crate::database::db_sqlite::SqliteAdapter;
 use redb;
 
+pub use bolt::{BoltError, BoltImport, import_bolt};
+
+mod bolt;
+mod db_memory;
 mod db_redb;
+mod db_redis;
+mod db_sqlite;
+mod migrate;
 
 const WORKER_BUCKETKEY: &str = "workers";
 const STATUS_BUCKETKEY: &str = "mirror_status";
+// status keys by worker, see `worker_index_prefix`
+const WORKER_INDEX_BUCKETKEY: &str = "mirror_status_by_worker";
+// statuses of deleted workers, kept when asked to
+const ARCHIVE_BUCKETKEY: &str = "mirror_status_archive";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DbType {
-    // TODO current only redb is supported
     Redb,
-    // Redis,
+    // kept in memory only, for tests and throwaway managers
+    Memory,
+    Redis,
+    Sqlite,
     // Badger,
     // LevelDb,
 }
@@ -30,9 +51,10 @@ impl FromStr for DbType {
     type Err = AdapterError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.trim().to_ascii_lowercase().as_str() {
-            // TODO current only redb is supported
             "redb" => Ok(DbType::Redb),
-            // "redis" => Ok(DbType::Redis),
+            "memory" => Ok(DbType::Memory),
+            "redis" => Ok(DbType::Redis),
+            "sqlite" => Ok(DbType::Sqlite),
             // "badger" => Ok(DbType::Badger),
             // "leveldb" => Ok(DbType::LevelDb),
             _ => Err(AdapterError::UnsupportedDbType(s.into())),
@@ -47,9 +69,23 @@ pub enum AdapterError {
     InitError(String),
     #[error("create bucket: {0}, error: {1}")]
     CreateBucketError(String, String),
-    // This should be more specific in real implementation
-    #[error("anyhow error: {0}")]
-    Anyhow(String),
+    #[error("invalid workerID {0}")]
+    WorkerNotFound(String),
+    #[error("no mirror '{mirror}' exists in worker '{worker}'")]
+    MirrorNotFound { worker: String, mirror: String },
+    #[error("corrupt record {key}: {source}")]
+    CorruptRecord {
+        key: String,
+        source: serde_json::Error,
+    },
+    #[error("json marshal error: {0}")]
+    Serialization(#[source] serde_json::Error),
+    #[error("database schema version {found} is newer than the supported {supported}")]
+    SchemaTooNew { found: u32, supported: u32 },
+    #[error("database unavailable: {0}")]
+    BackendUnavailable(String),
+    #[error("internal error: {0}")]
+    Internal(String),
     #[error(transparent)]
     RdbError(#[from] redb::Error),
     #[error(transparent)]
@@ -62,14 +98,56 @@ pub enum AdapterError {
     RdbCommitError(#[from] redb::CommitError),
     #[error(transparent)]
     RdbStorageError(#[from] redb::StorageError),
+    #[error(transparent)]
+    RedisError(#[from] redis::RedisError),
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
     // TODO: more error variants
 }
 
+impl AdapterError {
+    /// A code for API responses that stays the same when messages change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AdapterError::UnsupportedDbType(_) => "unsupported_db_type",
+            AdapterError::InitError(_) | AdapterError::CreateBucketError(..) => "init_failed",
+            AdapterError::WorkerNotFound(_) => "worker_not_found",
+            AdapterError::MirrorNotFound { .. } => "mirror_not_found",
+            AdapterError::CorruptRecord { .. } => "corrupt_record",
+            AdapterError::Serialization(_) => "serialization_failed",
+            AdapterError::SchemaTooNew { .. } => "schema_too_new",
+            AdapterError::BackendUnavailable(_) => "backend_unavailable",
+            AdapterError::Internal(_) => "internal",
+            _ => "backend_error",
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            AdapterError::WorkerNotFound(_) | AdapterError::MirrorNotFound { .. }
+        )
+    }
+}
+
+fn decode<T: DeserializeOwned>(key: &str, bytes: &[u8]) -> Result<T, AdapterError> {
+    serde_json::from_slice(bytes).map_err(|source| AdapterError::CorruptRecord {
+        key: key.to_string(),
+        source,
+    })
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, AdapterError> {
+    serde_json::to_vec(value).map_err(AdapterError::Serialization)
+}
+
 pub trait DbAdapterTrait: Send + Sync {
     fn init(&self) -> Result<(), AdapterError>;
     fn list_workers(&self) -> Result<Vec<WorkerStatus>, AdapterError>;
     fn get_worker(&self, worker_id: &str) -> Result<WorkerStatus, AdapterError>;
-    fn delete_worker(&self, worker_id: &str) -> Result<(), AdapterError>;
+    /// Delete a worker with its mirror statuses, which are moved to an archive
+    /// instead when `keep_history` is set.
+    fn delete_worker(&self, worker_id: &str, keep_history: bool) -> Result<(), AdapterError>;
     fn create_worker(&self, w: WorkerStatus) -> Result<WorkerStatus, AdapterError>;
     fn refresh_worker(&self, worker_id: &str) -> Result<WorkerStatus, AdapterError>;
     fn update_mirror_status(
@@ -86,38 +164,101 @@ pub trait DbAdapterTrait: Send + Sync {
     fn list_mirror_status(&self, worker_id: &str) -> Result<Vec<MirrorStatus>, AdapterError>;
     fn list_all_mirror_status(&self) -> Result<Vec<MirrorStatus>, AdapterError>;
     fn flush_disabled_jobs(&self) -> Result<(), AdapterError>;
+    /// Store all of `workers` and `statuses`, replacing the records with the
+    /// same keys, or none of them on error.
+    fn import(
+        &self,
+        workers: &[WorkerStatus],
+        statuses: &[MirrorStatus],
+    ) -> Result<(), AdapterError>;
     fn close(&self) -> Result<(), AdapterError>;
 }
+/// A write in a batch given to [`KvAdapterTrait::write_batch`].
+enum KvOp<'a> {
+    Put {
+        bucket: &'a str,
+        key: String,
+        value: Vec<u8>,
+    },
+    Delete {
+        bucket: &'a str,
+        key: String,
+    },
+}
+
+/// The reads of a plan given to [`KvAdapterTrait::update`].
+trait KvRead {
+    fn get(&mut self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError>;
+    fn get_all(&mut self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError>;
+    fn scan_prefix(
+        &mut self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError>;
+}
+
+/// Decides the writes of an update from what it reads.
+type KvPlan<'a> = dyn FnMut(&mut dyn KvRead) -> Result<Vec<KvOp<'static>>, AdapterError> + 'a;
+
 trait KvAdapterTrait: Send + Sync {
     fn init_bucket(&self, bucket: &str) -> Result<(), AdapterError>;
     // TODO should be bytes return
     fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, AdapterError>;
     // TODO should be bytes return
     fn get_all(&self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, AdapterError>;
+    /// The entries whose key starts with `prefix`, in key order.
+    fn scan_prefix(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, AdapterError>;
     fn put(&self, bucket: &str, key: &str, value: &[u8]) -> Result<(), AdapterError>;
-    fn delete(&self, bucket: &str, key: &str) -> Result<(), AdapterError>;
+    /// Apply all of `ops` in order, or none of them on error.
+    fn write_batch(&self, ops: &[KvOp<'_>]) -> Result<(), AdapterError>;
+    /// Apply the writes `plan` decides from what it reads, all or none, as if
+    /// nothing was written to the `watched` buckets in between; `plan` runs
+    /// again when something was.
+    fn update(&self, watched: &[&str], plan: &mut KvPlan<'_>) -> Result<(), AdapterError>;
     fn close(&self) -> Result<(), AdapterError>;
 }
 
+// Names may contain '/', so the worker ID is length-prefixed to keep the
+// prefix of one worker from matching the entries of another.
+fn worker_index_prefix(worker_id: &str) -> String {
+    format!("{}:{}/", worker_id.len(), worker_id)
+}
+
+fn worker_index_key(worker_id: &str, mirror_id: &str) -> String {
+    worker_index_prefix(worker_id) + mirror_id
+}
+
+fn status_key(worker_id: &str, mirror_id: &str) -> String {
+    format!("{}/{}", mirror_id, worker_id)
+}
+
 struct KvDBAdapter {
     inner: Box<dyn KvAdapterTrait>,
 }
 
 impl KvDBAdapter {
+    fn new(inner: Box<dyn KvAdapterTrait>) -> Self {
+        KvDBAdapter { inner }
+    }
+
     fn init(&self) -> Result<(), AdapterError> {
         self.inner.init_bucket(WORKER_BUCKETKEY)?;
         self.inner.init_bucket(STATUS_BUCKETKEY)?;
-        Ok(())
+        self.inner.init_bucket(WORKER_INDEX_BUCKETKEY)?;
+        self.inner.init_bucket(ARCHIVE_BUCKETKEY)?;
+        migrate::migrate(self.inner.as_ref())
     }
 
     fn list_workers(&self) -> Result<Vec<WorkerStatus>, AdapterError> {
         let workers_map = self.inner.get_all(WORKER_BUCKETKEY)?;
         let mut workers = Vec::new();
 
-        for (_, v) in workers_map {
-            let w: WorkerStatus = serde_json::from_slice(&v)
-                .map_err(|e| AdapterError::Anyhow(format!("json unmarshal error: {}", e)))?;
-            workers.push(w);
+        for (k, v) in workers_map {
+            workers.push(decode(&k, &v)?);
         }
         Ok(workers)
     }
@@ -125,40 +266,71 @@ impl KvDBAdapter {
     fn get_worker(&self, worker_id: &str) -> Result<WorkerStatus, AdapterError> {
         let v = self.inner.get(WORKER_BUCKETKEY, worker_id)?;
         let Some(bytes) = v else {
-            return Err(AdapterError::Anyhow(format!(
-                "invalid workerID {}",
-                worker_id
-            )));
+            return Err(AdapterError::WorkerNotFound(worker_id.to_string()));
         };
-
-        let w: WorkerStatus = serde_json::from_slice(&bytes)
-            .map_err(|e| AdapterError::Anyhow(format!("json unmarshal error: {}", e)))?;
-        Ok(w)
+        decode(worker_id, &bytes)
     }
 
-    fn delete_worker(&self, worker_id: &str) -> Result<(), AdapterError> {
-        // Check existence first to match Go behavior (optional but good for error reporting)
-        let v = self.inner.get(WORKER_BUCKETKEY, worker_id)?;
-        if v.is_none() {
-            return Err(AdapterError::Anyhow(format!(
-                "invalid workerID {}",
-                worker_id
-            )));
-        }
-        self.inner.delete(WORKER_BUCKETKEY, worker_id)
+    fn delete_worker(&self, worker_id: &str, keep_history: bool) -> Result<(), AdapterError> {
+        let prefix = worker_index_prefix(worker_id);
+        // a status written meanwhile, by this manager or another, makes the
+        // scan stale and the deletion start over
+        self.inner
+            .update(&[WORKER_BUCKETKEY, WORKER_INDEX_BUCKETKEY], &mut |kv| {
+                // Check existence first to match Go behavior (optional but good for error reporting)
+                if kv.get(WORKER_BUCKETKEY, worker_id)?.is_none() {
+                    return Err(AdapterError::WorkerNotFound(worker_id.to_string()));
+                }
+                let mut ops = vec![KvOp::Delete {
+                    bucket: WORKER_BUCKETKEY,
+                    key: worker_id.to_string(),
+                }];
+                for (k, id) in kv.scan_prefix(WORKER_INDEX_BUCKETKEY, &prefix)? {
+                    let id = String::from_utf8_lossy(&id).into_owned();
+                    if keep_history && let Some(v) = kv.get(STATUS_BUCKETKEY, &id)? {
+                        ops.push(KvOp::Put {
+                            bucket: ARCHIVE_BUCKETKEY,
+                            key: id.clone(),
+                            value: v,
+                        });
+                    }
+                    ops.push(KvOp::Delete {
+                        bucket: WORKER_INDEX_BUCKETKEY,
+                        key: k,
+                    });
+                    ops.push(KvOp::Delete {
+                        bucket: STATUS_BUCKETKEY,
+                        key: id,
+                    });
+                }
+                Ok(ops)
+            })
     }
 
     fn create_worker(&self, w: WorkerStatus) -> Result<WorkerStatus, AdapterError> {
-        let v = serde_json::to_vec(&w)
-            .map_err(|e| AdapterError::Anyhow(format!("json marshal error: {}", e)))?;
+        let v = encode(&w)?;
         self.inner.put(WORKER_BUCKETKEY, &w.id, &v)?;
         Ok(w)
     }
 
     fn refresh_worker(&self, worker_id: &str) -> Result<WorkerStatus, AdapterError> {
-        let mut w = self.get_worker(worker_id)?;
-        w.last_online = chrono::Utc::now();
-        self.create_worker(w)
+        let mut refreshed = None;
+        // not to bring back a worker deleted meanwhile
+        self.inner.update(&[WORKER_BUCKETKEY], &mut |kv| {
+            let Some(bytes) = kv.get(WORKER_BUCKETKEY, worker_id)? else {
+                return Err(AdapterError::WorkerNotFound(worker_id.to_string()));
+            };
+            let mut w: WorkerStatus = decode(worker_id, &bytes)?;
+            w.last_online = chrono::Utc::now();
+            let op = KvOp::Put {
+                bucket: WORKER_BUCKETKEY,
+                key: w.id.clone(),
+                value: encode(&w)?,
+            };
+            refreshed = Some(w);
+            Ok(vec![op])
+        })?;
+        refreshed.ok_or_else(|| AdapterError::Internal("worker refresh did not run".into()))
     }
 
     fn update_mirror_status(
@@ -167,10 +339,8 @@ impl KvDBAdapter {
         mirror_id: &str,
         status: MirrorStatus,
     ) -> Result<MirrorStatus, AdapterError> {
-        let id = format!("{}/{}", mirror_id, worker_id);
-        let v = serde_json::to_vec(&status)
-            .map_err(|e| AdapterError::Anyhow(format!("json marshal error: {}", e)))?;
-        self.inner.put(STATUS_BUCKETKEY, &id, &v)?;
+        self.inner
+            .write_batch(&status_ops(worker_id, mirror_id, &status)?)?;
         Ok(status)
     }
 
@@ -179,33 +349,29 @@ impl KvDBAdapter {
         worker_id: &str,
         mirror_id: &str,
     ) -> Result<MirrorStatus, AdapterError> {
-        let id = format!("{}/{}", mirror_id, worker_id);
+        let id = status_key(worker_id, mirror_id);
         let v = self.inner.get(STATUS_BUCKETKEY, &id)?;
         match v {
-            Some(bytes) => {
-                let m: MirrorStatus = serde_json::from_slice(&bytes)
-                    .map_err(|e| AdapterError::Anyhow(format!("json unmarshal error: {}", e)))?;
-                Ok(m)
-            }
-            None => Err(AdapterError::Anyhow(format!(
-                "no mirror '{}' exists in worker '{}'",
-                mirror_id, worker_id
-            ))),
+            Some(bytes) => decode(&id, &bytes),
+            None => Err(AdapterError::MirrorNotFound {
+                worker: worker_id.to_string(),
+                mirror: mirror_id.to_string(),
+            }),
         }
     }
 
     fn list_mirror_status(&self, worker_id: &str) -> Result<Vec<MirrorStatus>, AdapterError> {
-        let vals = self.inner.get_all(STATUS_BUCKETKEY)?;
+        let prefix = worker_index_prefix(worker_id);
+        let index = self.inner.scan_prefix(WORKER_INDEX_BUCKETKEY, &prefix)?;
         let mut result = Vec::new();
 
-        for (k, v) in vals {
-            // key format: mirrorID/workerID
-            let parts: Vec<&str> = k.split('/').collect();
-            if parts.len() > 1 && parts[1] == worker_id {
-                let m: MirrorStatus = serde_json::from_slice(&v)
-                    .map_err(|e| AdapterError::Anyhow(format!("json unmarshal error: {}", e)))?;
-                result.push(m);
-            }
+        for (_, id) in index {
+            let id = String::from_utf8_lossy(&id);
+            // the index may outlive a status removed by an older version
+            let Some(v) = self.inner.get(STATUS_BUCKETKEY, &id)? else {
+                continue;
+            };
+            result.push(decode(&id, &v)?);
         }
         Ok(result)
     }
@@ -214,25 +380,65 @@ impl KvDBAdapter {
         let vals = self.inner.get_all(STATUS_BUCKETKEY)?;
         let mut result = Vec::new();
 
-        for (_, v) in vals {
-            let m: MirrorStatus = serde_json::from_slice(&v)
-                .map_err(|e| AdapterError::Anyhow(format!("json unmarshal error: {}", e)))?;
-            result.push(m);
+        for (k, v) in vals {
+            result.push(decode(&k, &v)?);
         }
         Ok(result)
     }
 
     fn flush_disabled_jobs(&self) -> Result<(), AdapterError> {
-        let vals = self.inner.get_all(STATUS_BUCKETKEY)?;
-        for (k, v) in vals {
-            let m: MirrorStatus = serde_json::from_slice(&v)
-                .map_err(|e| AdapterError::Anyhow(format!("json unmarshal error: {}", e)))?;
-
-            if m.status == hustsync_internal::status::SyncStatus::Disabled || m.name.is_empty() {
-                self.inner.delete(STATUS_BUCKETKEY, &k)?;
+        // a job enabled again meanwhile is not flushed, and a crash must not
+        // leave some of the jobs flushed
+        self.inner.update(&[STATUS_BUCKETKEY], &mut |kv| {
+            let vals = kv.get_all(STATUS_BUCKETKEY)?;
+            let mut flushed = HashSet::new();
+            for (k, v) in vals {
+                let m: MirrorStatus = decode(&k, &v)?;
+
+                if m.status == hustsync_internal::status::SyncStatus::Disabled || m.name.is_empty()
+                {
+                    flushed.insert(k.into_bytes());
+                }
+            }
+            if flushed.is_empty() {
+                return Ok(Vec::new());
+            }
+            let mut ops = Vec::new();
+            for (k, id) in kv.get_all(WORKER_INDEX_BUCKETKEY)? {
+                if flushed.contains(&id) {
+                    ops.push(KvOp::Delete {
+                        bucket: WORKER_INDEX_BUCKETKEY,
+                        key: k,
+                    });
+                }
+            }
+            for id in flushed {
+                ops.push(KvOp::Delete {
+                    bucket: STATUS_BUCKETKEY,
+                    key: String::from_utf8_lossy(&id).into_owned(),
+                });
             }
+            Ok(ops)
+        })
+    }
+
+    fn import(
+        &self,
+        workers: &[WorkerStatus],
+        statuses: &[MirrorStatus],
+    ) -> Result<(), AdapterError> {
+        let mut ops = Vec::new();
+        for w in workers {
+            ops.push(KvOp::Put {
+                bucket: WORKER_BUCKETKEY,
+                key: w.id.clone(),
+                value: encode(w)?,
+            });
         }
-        Ok(())
+        for m in statuses {
+            ops.extend(status_ops(&m.worker, &m.name, m)?);
+        }
+        self.inner.write_batch(&ops)
     }
 
     fn close(&self) -> Result<(), AdapterError> {
@@ -240,6 +446,27 @@ impl KvDBAdapter {
     }
 }
 
+// the writes storing a mirror status, along with its entry in the worker index
+fn status_ops(
+    worker_id: &str,
+    mirror_id: &str,
+    status: &MirrorStatus,
+) -> Result<[KvOp<'static>; 2], AdapterError> {
+    let id = status_key(worker_id, mirror_id);
+    Ok([
+        KvOp::Put {
+            bucket: WORKER_INDEX_BUCKETKEY,
+            key: worker_index_key(worker_id, mirror_id),
+            value: id.clone().into_bytes(),
+        },
+        KvOp::Put {
+            bucket: STATUS_BUCKETKEY,
+            key: id,
+            value: encode(status)?,
+        },
+    ])
+}
+
 impl DbAdapterTrait for KvDBAdapter {
     fn init(&self) -> Result<(), AdapterError> {
         KvDBAdapter::init(self)
@@ -253,8 +480,8 @@ impl DbAdapterTrait for KvDBAdapter {
         KvDBAdapter::get_worker(self, worker_id)
     }
 
-    fn delete_worker(&self, worker_id: &str) -> Result<(), AdapterError> {
-        KvDBAdapter::delete_worker(self, worker_id)
+    fn delete_worker(&self, worker_id: &str, keep_history: bool) -> Result<(), AdapterError> {
+        KvDBAdapter::delete_worker(self, worker_id, keep_history)
     }
 
     fn create_worker(&self, w: WorkerStatus) -> Result<WorkerStatus, AdapterError> {
@@ -294,6 +521,14 @@ impl DbAdapterTrait for KvDBAdapter {
         KvDBAdapter::flush_disabled_jobs(self)
     }
 
+    fn import(
+        &self,
+        workers: &[WorkerStatus],
+        statuses: &[MirrorStatus],
+    ) -> Result<(), AdapterError> {
+        KvDBAdapter::import(self, workers, statuses)
+    }
+
     fn close(&self) -> Result<(), AdapterError> {
         KvDBAdapter::close(self)
     }
@@ -308,11 +543,15 @@ pub fn make_db_adapter(
         DbType::Redb => {
             let inner_db = redb::Database::create(db_file.as_ref())?;
             let db = RedbAdapter { db: inner_db };
-            let kv = KvDBAdapter {
-                inner: Box::new(db),
-            };
-            Box::new(kv)
+            Box::new(KvDBAdapter::new(Box::new(db)))
         }
+        // db_file is not used, nothing outlives the manager
+        DbType::Memory => Box::new(KvDBAdapter::new(Box::new(MemoryAdapter::default()))),
+        // db_file holds the URL of the server
+        DbType::Redis => Box::new(KvDBAdapter::new(Box::new(RedisAdapter::connect(
+            db_file.as_ref(),
+        )?))),
+        DbType::Sqlite => Box::new(SqliteAdapter::open(db_file.as_ref())?),
     };
     Ok(adapter)
 }
@@ -2,6 +2,7 @@ mod common;
 mod config;
 pub mod database;
 mod server;
+mod status_file;
 
 pub use config::load_config;
 pub use server::{Manager, get_hustsync_manager};
@@ -1,15 +1,939 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
-use hustsync_config_parser::ManagerConfig;
+use axum::extract::{ConnectInfo, Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use hustsync_config_parser::{ManagerConfig, ManagerFileConfig, ManagerServerConfig};
+use hustsync_internal::msg::{
+    ClientCmd, CmdVerb, MirrorSchedules, MirrorStatus, WorkerCmd, WorkerStatus,
+};
+use hustsync_internal::status::SyncStatus;
+use hustsync_internal::status_web::WebMirrorStatus;
+use hustsync_internal::tls::{TlsConfig, TlsListener, TlsPeer};
+use hustsync_internal::util::{create_http_client, post_json};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::common::{debug_hustsync, error_hustsync, info_hustsync, trace_hustsync, warn_hustsync};
+use crate::database::{AdapterError, BoltError, BoltImport, DbAdapterTrait, make_db_adapter};
+use crate::status_file::write_status_file;
+
+const ERROR_KEY: &str = "error";
+const INFO_KEY: &str = "message";
+const CODE_KEY: &str = "code";
+// how often status_file is rewritten even without status updates
+const STATUS_DUMP_INTERVAL: Duration = Duration::from_secs(60);
+
+type ServeError = Box<dyn Error + Send + Sync>;
 
 pub struct Manager {
-    config: ManagerConfig, // ? restful_server
-                           // ? db adapter
-                           // ? sync semaphore
-                           // ? http client
+    config: ManagerConfig,
+    adapter: Arc<dyn DbAdapterTrait>,
+    // serializes read-modify-write cycles on mirror status
+    status_mu: Mutex<()>,
+    status_file: Option<PathBuf>,
+    // serializes snapshots of the statuses and their writes to status_file
+    dump_mu: Mutex<()>,
+    tls: Option<Arc<TlsConfig>>,
+    // otherwise only a bearer given is checked, as tunasync workers send none
+    require_worker_token: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteWorkerParams {
+    // archive the statuses of the worker instead of dropping them
+    #[serde(default)]
+    keep_history: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenMsg {
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SizeMsg {
+    name: String,
+    size: String,
 }
 
 pub fn get_hustsync_manager(config: ManagerConfig) -> Result<Manager, Box<dyn Error>> {
-    // ? set manager restful mode ?
-    Ok(Manager { config })
+    let defaults = ManagerFileConfig::default();
+    let files = config.files.as_ref();
+    let db_type = files
+        .and_then(|f| f.db_type.clone())
+        .or(defaults.db_type)
+        .unwrap_or_default();
+    let db_file = files
+        .and_then(|f| f.db_file.clone())
+        .or(defaults.db_file)
+        .unwrap_or_default();
+    let status_file = files
+        .and_then(|f| f.status_file.clone())
+        .filter(|f| !f.is_empty())
+        .map(PathBuf::from);
+
+    let server = config.server.as_ref();
+    let server_defaults = ManagerServerConfig::default();
+    let require_worker_token = server
+        .and_then(|s| s.require_worker_token)
+        .or(server_defaults.require_worker_token)
+        .unwrap_or_default();
+    let mut tls = TlsConfig::from_pair(
+        server.and_then(|s| s.ssl_cert.as_deref()),
+        server.and_then(|s| s.ssl_key.as_deref()),
+    )?;
+    // over HTTPS, workers prove themselves with a certificate signed by ca_cert
+    if let Some(ca_cert) = files
+        .and_then(|f| f.ca_cert.as_deref())
+        .filter(|c| !c.is_empty())
+    {
+        tls = tls.map(|t| t.with_client_ca(ca_cert)).transpose()?;
+    }
+
+    let adapter = make_db_adapter(&db_type, &db_file)?;
+    adapter.init()?;
+
+    Ok(Manager {
+        config,
+        adapter: Arc::from(adapter),
+        status_mu: Mutex::new(()),
+        status_file,
+        dump_mu: Mutex::new(()),
+        tls: tls.map(Arc::new),
+        require_worker_token,
+    })
+}
+
+impl Manager {
+    /// The `(addr, port)` pair the manager listens on, falling back to the defaults.
+    pub fn listen_addr(&self) -> (String, u16) {
+        let defaults = ManagerServerConfig::default();
+        let server = self.config.server.as_ref();
+        let addr = server
+            .and_then(|s| s.addr.clone())
+            .or(defaults.addr)
+            .unwrap_or_default();
+        let port = server
+            .and_then(|s| s.port)
+            .or(defaults.port)
+            .unwrap_or_default();
+        (addr, port)
+    }
+
+    /// Copy the records of a Go tunasync manager database into ours.
+    pub fn import_bolt(&self, path: &std::path::Path) -> Result<BoltImport, BoltError> {
+        let imported = crate::database::import_bolt(path, self.adapter.as_ref())?;
+        self.dump_status();
+        Ok(imported)
+    }
+
+    /// Bind to the configured address and serve until interrupted.
+    pub async fn run(self) -> Result<(), ServeError> {
+        let (addr, port) = self.listen_addr();
+        let listener = TcpListener::bind((addr.as_str(), port)).await?;
+        let proto = if self.tls.is_some() { "https" } else { "http" };
+        info_hustsync(&format!(
+            "hustsync manager listening on {}://{}:{}",
+            proto, addr, port
+        ));
+        self.serve(listener).await
+    }
+
+    /// Serve the REST API on an already bound listener until interrupted.
+    pub async fn serve(self, listener: TcpListener) -> Result<(), ServeError> {
+        let manager = Arc::new(self);
+        let app = Self::router(Arc::clone(&manager));
+        let dumper = tokio::spawn(dump_status_loop(Arc::clone(&manager)));
+        let result = match &manager.tls {
+            Some(tls) => {
+                let reloader = tokio::spawn(reload_tls_on_hangup(Arc::clone(tls)));
+                let listener = TlsListener::new(listener, Arc::clone(tls))?;
+                let result = axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<TlsPeer>(),
+                )
+                .with_graceful_shutdown(shutdown_signal())
+                .await;
+                reloader.abort();
+                result
+            }
+            None => {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<TlsPeer>(),
+                )
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+            }
+        };
+        dumper.abort();
+        result?;
+        run_blocking(&manager, |m| {
+            m.dump_status();
+            m.adapter.close()
+        })
+        .await?;
+        Ok(())
+    }
+
+    fn router(manager: Arc<Manager>) -> Router {
+        let client_cert_layer =
+            || middleware::from_fn_with_state(Arc::clone(&manager), require_client_cert);
+        let validate_worker_layer =
+            || middleware::from_fn_with_state(Arc::clone(&manager), validate_worker);
+        let worker_token_layer =
+            || middleware::from_fn_with_state(Arc::clone(&manager), require_worker_token);
+
+        // routes under /workers/{id} require the worker to be registered, and
+        // the calls only workers make require their client certificate and token too
+        let worker_routes = Router::new()
+            .route("/workers/{id}", delete(delete_worker))
+            .route("/workers/{id}/jobs", get(list_jobs_of_worker))
+            .route("/workers/{id}/jobs/{job}/size", post(update_mirror_size))
+            .route_layer(validate_worker_layer());
+        let worker_calls = Router::new()
+            .route("/workers/{id}/heartbeat", post(heartbeat_worker))
+            .route("/workers/{id}/jobs/{job}", post(update_job_of_worker))
+            .route("/workers/{id}/schedules", post(update_schedules_of_worker))
+            .route("/workers/{id}/token", post(rotate_worker_token))
+            .route_layer(worker_token_layer())
+            .route_layer(validate_worker_layer())
+            .route_layer(client_cert_layer());
+
+        Router::new()
+            .route("/ping", get(ping))
+            .route("/jobs", get(list_all_jobs))
+            .route("/jobs/disabled", delete(flush_disabled_jobs))
+            .route(
+                "/workers",
+                get(list_workers).merge(post(register_worker).route_layer(client_cert_layer())),
+            )
+            .route("/cmd", post(handle_client_cmd))
+            .merge(worker_routes)
+            .merge(worker_calls)
+            .with_state(manager)
+    }
+
+    fn list_workers(&self) -> Result<Vec<WorkerStatus>, AdapterError> {
+        let workers = self.adapter.list_workers()?;
+        Ok(workers.into_iter().map(strip_token).collect())
+    }
+
+    /// Record `worker` as online, issuing it a token unless it brings its own.
+    fn register_worker(&self, mut worker: WorkerStatus) -> Result<WorkerStatus, AdapterError> {
+        let now = Utc::now();
+        worker.last_online = now;
+        worker.last_register = now;
+        if worker.token.is_empty() {
+            worker.token = issue_token()?;
+        }
+        let worker = self.adapter.create_worker(worker)?;
+        info_hustsync(&format!("Worker <{}> registered", worker.id));
+        Ok(worker)
+    }
+
+    /// Replace the token of a worker, issuing a new one if `token` is empty.
+    fn rotate_token(&self, worker_id: &str, token: String) -> Result<WorkerStatus, AdapterError> {
+        let mut worker = self.adapter.get_worker(worker_id)?;
+        worker.token = if token.is_empty() {
+            issue_token()?
+        } else {
+            token
+        };
+        let worker = self.adapter.create_worker(worker)?;
+        info_hustsync(&format!("Token of worker <{}> rotated", worker_id));
+        Ok(worker)
+    }
+
+    fn update_job_of_worker(
+        &self,
+        worker_id: &str,
+        mut status: MirrorStatus,
+    ) -> Result<MirrorStatus, AdapterError> {
+        let _guard = self
+            .status_mu
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.adapter.refresh_worker(worker_id)?;
+        let cur_status = self
+            .adapter
+            .get_mirror_status(worker_id, &status.name)
+            .or_else(|e| e.is_not_found().then(MirrorStatus::default).ok_or(e))?;
+
+        let now = Utc::now();
+        status.last_started = if status.status == SyncStatus::PreSyncing
+            && cur_status.status != SyncStatus::PreSyncing
+        {
+            now
+        } else {
+            cur_status.last_started
+        };
+        // only a successful sync bumps last_update
+        status.last_update = if status.status == SyncStatus::Success {
+            now
+        } else {
+            cur_status.last_update
+        };
+        status.last_ended = if matches!(status.status, SyncStatus::Success | SyncStatus::Failed) {
+            now
+        } else {
+            cur_status.last_ended
+        };
+        // only a meaningful size overrides the recorded one
+        if has_known_size(&cur_status.size) && !has_known_size(&status.size) {
+            status.size = cur_status.size;
+        }
+
+        match status.status {
+            SyncStatus::Syncing => info_hustsync(&format!(
+                "Job [{}] @<{}> starts syncing",
+                status.name, status.worker
+            )),
+            s => info_hustsync(&format!(
+                "Job [{}] @<{}> {:?}",
+                status.name, status.worker, s
+            )),
+        }
+
+        let name = status.name.clone();
+        self.adapter.update_mirror_status(worker_id, &name, status)
+    }
+
+    fn update_mirror_size(
+        &self,
+        worker_id: &str,
+        msg: SizeMsg,
+    ) -> Result<MirrorStatus, AdapterError> {
+        let _guard = self
+            .status_mu
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut status = self.adapter.get_mirror_status(worker_id, &msg.name)?;
+        if has_known_size(&msg.size) {
+            status.size = msg.size;
+        }
+        info_hustsync(&format!(
+            "Mirror size of [{}] @<{}>: {}",
+            status.name, status.worker, status.size
+        ));
+        self.adapter
+            .update_mirror_status(worker_id, &msg.name, status)
+    }
+
+    /// Record the status a command implies, even before the worker applies it.
+    fn apply_client_cmd(
+        &self,
+        verb: CmdVerb,
+        worker_id: &str,
+        mirror_id: &str,
+    ) -> Result<(), AdapterError> {
+        let status = match verb {
+            CmdVerb::Disable => SyncStatus::Disabled,
+            CmdVerb::Stop => SyncStatus::Paused,
+            _ => return Ok(()),
+        };
+        let _guard = self
+            .status_mu
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut cur_status = match self.adapter.get_mirror_status(worker_id, mirror_id) {
+            Ok(status) => status,
+            Err(e) if e.is_not_found() => MirrorStatus {
+                name: mirror_id.to_string(),
+                worker: worker_id.to_string(),
+                ..MirrorStatus::default()
+            },
+            Err(e) => return Err(e),
+        };
+        cur_status.status = status;
+        self.adapter
+            .update_mirror_status(worker_id, mirror_id, cur_status)?;
+        Ok(())
+    }
+
+    /// Write the status of all mirrors to `status_file`, if one is configured.
+    fn dump_status(&self) {
+        let Some(path) = &self.status_file else {
+            return;
+        };
+        // held while listing too, so that an older snapshot never overwrites a newer one
+        let _guard = self.dump_mu.lock().unwrap_or_else(PoisonError::into_inner);
+        let statuses = match self.adapter.list_all_mirror_status() {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                warn_hustsync(&format!("failed to list mirror status: {}", e));
+                return;
+            }
+        };
+        let web: Vec<WebMirrorStatus> = statuses.into_iter().map(WebMirrorStatus::from).collect();
+        if let Err(e) = write_status_file(path, &web) {
+            warn_hustsync(&format!(
+                "failed to write status file {}: {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    /// The CA certificate to verify workers with, if any.
+    fn ca_cert(&self) -> Option<String> {
+        self.config
+            .files
+            .as_ref()
+            .and_then(|f| f.ca_cert.clone())
+            .filter(|c| !c.is_empty())
+    }
+
+    fn update_schedules_of_worker(
+        &self,
+        worker_id: &str,
+        schedules: MirrorSchedules,
+    ) -> Result<(), AdapterError> {
+        let _guard = self
+            .status_mu
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for schedule in schedules.schedules {
+            let Ok(mut status) = self.adapter.get_mirror_status(worker_id, &schedule.name) else {
+                warn_hustsync(&format!(
+                    "failed to get status of mirror {} @<{}>",
+                    schedule.name, worker_id
+                ));
+                continue;
+            };
+            if status.name.is_empty() || status.next_schedule == schedule.next_schedule {
+                continue;
+            }
+            debug_hustsync(&format!(
+                "Job [{}] @<{}> next scheduled at {}",
+                schedule.name, worker_id, schedule.next_schedule
+            ));
+            status.next_schedule = schedule.next_schedule;
+            self.adapter
+                .update_mirror_status(worker_id, &schedule.name, status)?;
+        }
+        Ok(())
+    }
+}
+
+// never leak worker tokens through the public API
+fn strip_token(worker: WorkerStatus) -> WorkerStatus {
+    WorkerStatus {
+        token: String::new(),
+        ..worker
+    }
+}
+
+/// A fresh random worker token, hex encoded.
+fn issue_token() -> Result<String, AdapterError> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| AdapterError::Internal(format!("failed to issue token: {}", e)))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+// compares in constant time, not to leak how much of a guess was right
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn has_known_size(size: &str) -> bool {
+    !size.is_empty() && size != "unknown"
+}
+
+/// Post a command to the worker listening on `url`, failing unless it accepts it.
+fn post_worker_cmd(url: &str, cmd: &WorkerCmd, ca_cert: Option<&str>) -> Result<(), String> {
+    let client = create_http_client(ca_cert).map_err(|e| e.to_string())?;
+    let resp = post_json(url, cmd, Some(&client)).map_err(|e| e.to_string())?;
+    let status = resp.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("{}: {}", status, resp.text().unwrap_or_default()))
+    }
+}
+
+fn error_json(code: StatusCode, msg: impl Into<String>) -> Response {
+    let msg = msg.into();
+    error_hustsync(&msg);
+    (code, Json(json!({ ERROR_KEY: msg }))).into_response()
+}
+
+fn coded_error_json(status: StatusCode, code: &str, msg: impl Into<String>) -> Response {
+    let msg = msg.into();
+    error_hustsync(&msg);
+    (status, Json(json!({ ERROR_KEY: msg, CODE_KEY: code }))).into_response()
+}
+
+/// Answer with the status and stable code of `e`, its message following `context`.
+fn adapter_error_json(e: &AdapterError, context: impl fmt::Display) -> Response {
+    let status = match e {
+        AdapterError::WorkerNotFound(_) | AdapterError::MirrorNotFound { .. } => {
+            StatusCode::NOT_FOUND
+        }
+        AdapterError::BackendUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    coded_error_json(status, e.code(), format!("{}: {}", context, e))
+}
+
+fn info_json(msg: impl Into<String>) -> Response {
+    (StatusCode::OK, Json(json!({ INFO_KEY: msg.into() }))).into_response()
+}
+
+/// Run `f` on the blocking thread pool, as adapters wait on disk or network
+/// I/O that would otherwise stall the other requests.
+async fn run_blocking<T, F>(manager: &Arc<Manager>, f: F) -> Result<T, AdapterError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Manager) -> Result<T, AdapterError> + Send + 'static,
+{
+    let manager = Arc::clone(manager);
+    tokio::task::spawn_blocking(move || f(&manager))
+        .await
+        .unwrap_or_else(|e| {
+            Err(AdapterError::Internal(format!(
+                "adapter call failed: {}",
+                e
+            )))
+        })
+}
+
+async fn dump_status_loop(manager: Arc<Manager>) {
+    let mut interval = tokio::time::interval(STATUS_DUMP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let _ = run_blocking(&manager, |m| {
+            m.dump_status();
+            Ok(())
+        })
+        .await;
+    }
+}
+
+/// Read the TLS certificate again on every SIGHUP, keeping open connections.
+async fn reload_tls_on_hangup(tls: Arc<TlsConfig>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error_hustsync(&format!("failed to listen for SIGHUP: {}", e));
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match tls.reload() {
+            Ok(()) => info_hustsync("TLS certificate reloaded"),
+            Err(e) => error_hustsync(&format!("failed to reload TLS certificate: {}", e)),
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error_hustsync(&format!("failed to listen for shutdown signal: {}", e));
+    }
+    info_hustsync("shutting down hustsync manager");
+}
+
+/// Turn away clients without a certificate signed by `ca_cert`, when the manager
+/// verifies them.
+async fn require_client_cert(
+    State(manager): State<Arc<Manager>>,
+    ConnectInfo(peer): ConnectInfo<TlsPeer>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let verifies_clients = manager.tls.as_ref().is_some_and(|t| t.verifies_clients());
+    if verifies_clients && !peer.client_verified {
+        return error_json(
+            StatusCode::FORBIDDEN,
+            format!(
+                "client {} has no certificate signed by ca_cert",
+                peer.remote_addr
+            ),
+        );
+    }
+    next.run(req).await
+}
+
+/// Turn away calls on behalf of a worker that lack its token as bearer.
+async fn require_worker_token(
+    State(manager): State<Arc<Manager>>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    req: Request,
+    next: Next,
+) -> Response {
+    let worker_id = params.get("id").map(String::as_str).unwrap_or_default();
+    let worker = match run_blocking(&manager, {
+        let worker_id = worker_id.to_string();
+        move |m| m.adapter.get_worker(&worker_id)
+    })
+    .await
+    {
+        Ok(worker) => worker,
+        Err(e) => return invalid_worker_json(worker_id, &e),
+    };
+    // workers registered before tokens were issued have none
+    let authorized = match bearer_token(&headers) {
+        Some(given) => worker.token.is_empty() || token_matches(&worker.token, given),
+        None => worker.token.is_empty() || !manager.require_worker_token,
+    };
+    if !authorized {
+        return error_json(
+            StatusCode::UNAUTHORIZED,
+            format!("invalid token for worker {}", worker_id),
+        );
+    }
+    next.run(req).await
+}
+
+async fn validate_worker(
+    State(manager): State<Arc<Manager>>,
+    Path(params): Path<HashMap<String, String>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let worker_id = params.get("id").map(String::as_str).unwrap_or_default();
+    let lookup = run_blocking(&manager, {
+        let worker_id = worker_id.to_string();
+        move |m| m.adapter.get_worker(&worker_id)
+    })
+    .await;
+    if let Err(e) = lookup {
+        return invalid_worker_json(worker_id, &e);
+    }
+    next.run(req).await
+}
+
+// an unknown worker in the path is a bad request, as in tunasync
+fn invalid_worker_json(worker_id: &str, e: &AdapterError) -> Response {
+    if e.is_not_found() {
+        return coded_error_json(
+            StatusCode::BAD_REQUEST,
+            e.code(),
+            format!("invalid workerID {}", worker_id),
+        );
+    }
+    adapter_error_json(e, format!("failed to look up worker {}", worker_id))
+}
+
+async fn ping() -> Response {
+    trace_hustsync("ping");
+    info_json("pong")
+}
+
+async fn list_all_jobs(State(manager): State<Arc<Manager>>) -> Response {
+    match run_blocking(&manager, |m| m.adapter.list_all_mirror_status()).await {
+        Ok(statuses) => {
+            let web: Vec<WebMirrorStatus> =
+                statuses.into_iter().map(WebMirrorStatus::from).collect();
+            Json(web).into_response()
+        }
+        Err(e) => adapter_error_json(&e, "failed to list all mirror status"),
+    }
+}
+
+async fn flush_disabled_jobs(State(manager): State<Arc<Manager>>) -> Response {
+    let flushed = run_blocking(&manager, |m| {
+        m.adapter.flush_disabled_jobs()?;
+        m.dump_status();
+        Ok(())
+    })
+    .await;
+    match flushed {
+        Ok(()) => info_json("flushed"),
+        Err(e) => adapter_error_json(&e, "failed to flush disabled jobs"),
+    }
+}
+
+async fn list_workers(State(manager): State<Arc<Manager>>) -> Response {
+    match run_blocking(&manager, |m| m.list_workers()).await {
+        Ok(workers) => Json(workers).into_response(),
+        Err(e) => adapter_error_json(&e, "failed to list workers"),
+    }
+}
+
+async fn register_worker(
+    State(manager): State<Arc<Manager>>,
+    headers: HeaderMap,
+    Json(mut worker): Json<WorkerStatus>,
+) -> Response {
+    if worker.id.is_empty() {
+        return error_json(StatusCode::BAD_REQUEST, "worker ID should not be empty");
+    }
+    let registered = match run_blocking(&manager, {
+        let worker_id = worker.id.clone();
+        move |m| m.adapter.get_worker(&worker_id)
+    })
+    .await
+    {
+        Ok(registered) => Some(registered),
+        Err(e) if e.is_not_found() => None,
+        Err(e) => return adapter_error_json(&e, "failed to register worker"),
+    };
+    // a registered worker has to prove it is the same one, and keeps its token;
+    // unless tokens are required, one that gives none registers again as tunasync does
+    if let Some(registered) = registered
+        && !registered.token.is_empty()
+    {
+        let given = bearer_token(&headers).unwrap_or(&worker.token);
+        let proven = if given.is_empty() {
+            !manager.require_worker_token
+        } else {
+            token_matches(&registered.token, given)
+        };
+        if !proven {
+            return error_json(
+                StatusCode::UNAUTHORIZED,
+                format!("worker {} is registered with another token", worker.id),
+            );
+        }
+        worker.token = registered.token;
+    }
+    match run_blocking(&manager, |m| m.register_worker(worker)).await {
+        Ok(worker) => Json(worker).into_response(),
+        Err(e) => adapter_error_json(&e, "failed to register worker"),
+    }
+}
+
+async fn rotate_worker_token(
+    State(manager): State<Arc<Manager>>,
+    Path(worker_id): Path<String>,
+    Json(msg): Json<TokenMsg>,
+) -> Response {
+    let rotated = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| m.rotate_token(&worker_id, msg.token)
+    })
+    .await;
+    match rotated {
+        Ok(worker) => Json(worker).into_response(),
+        Err(e) => adapter_error_json(
+            &e,
+            format!("failed to rotate token of worker {}", worker_id),
+        ),
+    }
+}
+
+async fn heartbeat_worker(
+    State(manager): State<Arc<Manager>>,
+    Path(worker_id): Path<String>,
+) -> Response {
+    let refreshed = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| m.adapter.refresh_worker(&worker_id)
+    })
+    .await;
+    match refreshed {
+        Ok(worker) => {
+            debug_hustsync(&format!("Worker <{}> is alive", worker_id));
+            Json(strip_token(worker)).into_response()
+        }
+        Err(e) => adapter_error_json(&e, format!("failed to refresh worker {}", worker_id)),
+    }
+}
+
+async fn delete_worker(
+    State(manager): State<Arc<Manager>>,
+    Path(worker_id): Path<String>,
+    Query(params): Query<DeleteWorkerParams>,
+) -> Response {
+    let deleted = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| {
+            m.adapter.delete_worker(&worker_id, params.keep_history)?;
+            m.dump_status();
+            Ok(())
+        }
+    })
+    .await;
+    match deleted {
+        Ok(()) => {
+            info_hustsync(&format!("Worker <{}> deleted", worker_id));
+            info_json("deleted")
+        }
+        Err(e) => adapter_error_json(&e, "failed to delete worker"),
+    }
+}
+
+async fn list_jobs_of_worker(
+    State(manager): State<Arc<Manager>>,
+    Path(worker_id): Path<String>,
+) -> Response {
+    let statuses = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| m.adapter.list_mirror_status(&worker_id)
+    })
+    .await;
+    match statuses {
+        Ok(statuses) => Json(statuses).into_response(),
+        Err(e) => adapter_error_json(&e, format!("failed to list jobs of worker {}", worker_id)),
+    }
+}
+
+async fn update_job_of_worker(
+    State(manager): State<Arc<Manager>>,
+    Path((worker_id, _job)): Path<(String, String)>,
+    Json(status): Json<MirrorStatus>,
+) -> Response {
+    if status.name.is_empty() {
+        return error_json(StatusCode::BAD_REQUEST, "mirror Name should not be empty");
+    }
+    let name = status.name.clone();
+    let updated = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| {
+            let new_status = m.update_job_of_worker(&worker_id, status)?;
+            m.dump_status();
+            Ok(new_status)
+        }
+    })
+    .await;
+    match updated {
+        Ok(new_status) => Json(new_status).into_response(),
+        Err(e) => adapter_error_json(
+            &e,
+            format!("failed to update job {} of worker {}", name, worker_id),
+        ),
+    }
+}
+
+async fn update_mirror_size(
+    State(manager): State<Arc<Manager>>,
+    Path((worker_id, _job)): Path<(String, String)>,
+    Json(msg): Json<SizeMsg>,
+) -> Response {
+    let name = msg.name.clone();
+    let updated = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| {
+            let new_status = m.update_mirror_size(&worker_id, msg)?;
+            m.dump_status();
+            Ok(new_status)
+        }
+    })
+    .await;
+    match updated {
+        Ok(new_status) => Json(new_status).into_response(),
+        Err(e) => adapter_error_json(
+            &e,
+            format!("failed to update size of mirror {} @<{}>", name, worker_id),
+        ),
+    }
+}
+
+async fn update_schedules_of_worker(
+    State(manager): State<Arc<Manager>>,
+    Path(worker_id): Path<String>,
+    Json(schedules): Json<MirrorSchedules>,
+) -> Response {
+    let updated = run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| {
+            m.update_schedules_of_worker(&worker_id, schedules)?;
+            m.dump_status();
+            Ok(())
+        }
+    })
+    .await;
+    match updated {
+        Ok(()) => info_json("updated"),
+        Err(e) => adapter_error_json(
+            &e,
+            format!("failed to update schedules of worker {}", worker_id),
+        ),
+    }
+}
+
+async fn handle_client_cmd(
+    State(manager): State<Arc<Manager>>,
+    Json(cmd): Json<ClientCmd>,
+) -> Response {
+    let worker_id = cmd.worker_id.clone();
+    if worker_id.is_empty() {
+        return error_json(StatusCode::BAD_REQUEST, "worker ID should not be empty");
+    }
+    let worker = match run_blocking(&manager, {
+        let worker_id = worker_id.clone();
+        move |m| m.adapter.get_worker(&worker_id)
+    })
+    .await
+    {
+        Ok(worker) => worker,
+        Err(e) if e.is_not_found() => {
+            return coded_error_json(
+                StatusCode::BAD_REQUEST,
+                e.code(),
+                format!("worker {} is not registered yet", worker_id),
+            );
+        }
+        Err(e) => return adapter_error_json(&e, "failed to look up worker"),
+    };
+
+    // the job is recorded disabled or paused even if the worker fails to apply the command
+    let applied = run_blocking(&manager, {
+        let (verb, mirror_id) = (cmd.cmd, cmd.mirror_id.clone());
+        let worker_id = worker_id.clone();
+        move |m| {
+            m.apply_client_cmd(verb, &worker_id, &mirror_id)?;
+            m.dump_status();
+            Ok(())
+        }
+    })
+    .await;
+    if let Err(e) = applied {
+        return adapter_error_json(
+            &e,
+            format!("failed to update status of mirror {}", cmd.mirror_id),
+        );
+    }
+
+    info_hustsync(&format!(
+        "Posting command '{:?} {}' to <{}>",
+        cmd.cmd, cmd.mirror_id, worker_id
+    ));
+    let worker_cmd = WorkerCmd::from(cmd);
+    let ca_cert = manager.ca_cert();
+    let url = worker.url.clone();
+    let result =
+        tokio::task::spawn_blocking(move || post_worker_cmd(&url, &worker_cmd, ca_cert.as_deref()))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(()) => info_json(format!("successfully send command to worker {}", worker_id)),
+        Err(e) => error_json(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "post command to worker {}({}) fail: {}",
+                worker_id, worker.url, e
+            ),
+        ),
+    }
 }
@@ -0,0 +1,24 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use hustsync_internal::status_web::WebMirrorStatus;
+
+/// Replace `path` with the given statuses as a JSON list.
+///
+/// The list is written to a temporary file next to `path` and renamed over it,
+/// so readers never see a partially written file.
+pub(crate) fn write_status_file(path: &Path, statuses: &[WebMirrorStatus]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "status file has no name"))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let content = serde_json::to_vec(statuses).map_err(io::Error::other)?;
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&content)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,237 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use hustsync_internal::status::SyncStatus;
+use hustsync_manager::database::{BoltError, import_bolt, make_db_adapter};
+use serde_json::json;
+
+const PAGE_SIZE: usize = 4096;
+const LEAF: u16 = 0x02;
+const BRANCH: u16 = 0x01;
+
+// a page image, padded to whole pages and counting the extra ones as overflow
+fn page(id: u64, flags: u16, count: usize, body: &[u8]) -> Vec<u8> {
+    let pages = (16 + body.len()).div_ceil(PAGE_SIZE);
+    let mut p = Vec::new();
+    p.extend(id.to_le_bytes());
+    p.extend(flags.to_le_bytes());
+    p.extend((count as u16).to_le_bytes());
+    p.extend(((pages - 1) as u32).to_le_bytes());
+    p.extend(body);
+    p.resize(pages * PAGE_SIZE, 0);
+    p
+}
+
+// elements of (is bucket, key, value), with keys and values after the headers
+fn leaf_body(elements: &[(bool, &[u8], &[u8])]) -> Vec<u8> {
+    let mut headers = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    for (i, (is_bucket, key, value)) in elements.iter().enumerate() {
+        let pos = (elements.len() - i) * 16 + data.len();
+        headers.extend(u32::from(*is_bucket).to_le_bytes());
+        headers.extend((pos as u32).to_le_bytes());
+        headers.extend((key.len() as u32).to_le_bytes());
+        headers.extend((value.len() as u32).to_le_bytes());
+        data.extend(*key);
+        data.extend(*value);
+    }
+    headers.extend(data);
+    headers
+}
+
+fn branch_body(children: &[(&[u8], u64)]) -> Vec<u8> {
+    let mut headers = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    for (i, (key, child)) in children.iter().enumerate() {
+        let pos = (children.len() - i) * 16 + data.len();
+        headers.extend((pos as u32).to_le_bytes());
+        headers.extend((key.len() as u32).to_le_bytes());
+        headers.extend(child.to_le_bytes());
+        data.extend(*key);
+    }
+    headers.extend(data);
+    headers
+}
+
+fn meta_page(id: u64, root: u64, txid: u64) -> Vec<u8> {
+    let mut meta = Vec::new();
+    meta.extend(0xED0C_DAEDu32.to_le_bytes());
+    meta.extend(2u32.to_le_bytes());
+    meta.extend((PAGE_SIZE as u32).to_le_bytes());
+    meta.extend(0u32.to_le_bytes());
+    meta.extend(root.to_le_bytes());
+    meta.extend(0u64.to_le_bytes());
+    // freelist and high water mark
+    meta.extend(4u64.to_le_bytes());
+    meta.extend(9u64.to_le_bytes());
+    meta.extend(txid.to_le_bytes());
+    let checksum = meta.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    meta.extend(checksum.to_le_bytes());
+    page(id, 0x04, 0, &meta)
+}
+
+fn bucket_header(root: u64) -> Vec<u8> {
+    let mut header = root.to_le_bytes().to_vec();
+    header.extend(0u64.to_le_bytes());
+    header
+}
+
+// a db of the Go manager, with the buckets and records it writes
+fn write_tunasync_db(path: &Path) {
+    let worker = json!({
+        "id": "test_worker1",
+        "url": "http://127.0.0.1:6000/",
+        "token": "secret",
+        "last_online": "2024-05-01T18:00:00+08:00",
+        "last_register": "2024-05-01T08:00:00Z",
+    })
+    .to_string();
+    // as written before last_started and next_schedule existed
+    let debian = json!({
+        "name": "debian",
+        "worker": "test_worker1",
+        "is_master": true,
+        "status": "failed",
+        "last_update": "2024-05-01T10:00:00Z",
+        "last_ended": "2024-05-01T10:00:00Z",
+        "upstream": "rsync://ftp.debian.org/debian/",
+        "size": "unknown",
+        "error_msg": "rsync error",
+    })
+    .to_string();
+    // too long for one page
+    let elvish = json!({
+        "name": "elvish",
+        "worker": "test_worker1",
+        "is_master": true,
+        "status": "success",
+        "last_update": "2024-05-01T10:00:00Z",
+        "last_started": "2024-05-01T09:59:00Z",
+        "last_ended": "2024-05-01T10:00:00Z",
+        "next_schedule": "2024-05-01T11:00:00Z",
+        "upstream": "rsync://rsync.elv.sh/elvish/",
+        "size": "1.33T",
+        "error_msg": "x".repeat(5000),
+    })
+    .to_string();
+
+    let mut inline_workers = bucket_header(0);
+    let workers_leaf = leaf_body(&[(false, b"test_worker1", worker.as_bytes())]);
+    inline_workers.extend(&page(0, LEAF, 1, &workers_leaf)[..16 + workers_leaf.len()]);
+    let root = leaf_body(&[
+        (true, b"mirror_status", &bucket_header(5)),
+        (true, b"workers", &inline_workers),
+    ]);
+
+    let mut file = Vec::new();
+    // the meta page of an older transaction, whose root is an empty leaf
+    file.extend(meta_page(0, 2, 1));
+    file.extend(meta_page(1, 3, 2));
+    file.extend(page(2, LEAF, 0, &[]));
+    file.extend(page(3, LEAF, 2, &root));
+    file.extend(page(4, 0x10, 0, &[]));
+    file.extend(page(
+        5,
+        BRANCH,
+        2,
+        &branch_body(&[(b"debian/test_worker1", 6), (b"elvish/test_worker1", 7)]),
+    ));
+    file.extend(page(
+        6,
+        LEAF,
+        1,
+        &leaf_body(&[(false, b"debian/test_worker1", debian.as_bytes())]),
+    ));
+    file.extend(page(
+        7,
+        LEAF,
+        1,
+        &leaf_body(&[(false, b"elvish/test_worker1", elvish.as_bytes())]),
+    ));
+    std::fs::write(path, file).unwrap();
+}
+
+#[test]
+fn tunasync_bolt_db_should_be_imported() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let bolt_file = tmp_dir.path().join("tunasync.db");
+    write_tunasync_db(&bolt_file);
+    let db_file = tmp_dir.path().join("manager.db");
+    let db = make_db_adapter("redb", db_file.to_string_lossy()).unwrap();
+    db.init().unwrap();
+
+    let imported = import_bolt(&bolt_file, db.as_ref()).unwrap();
+    assert_eq!(imported.workers, 1);
+    assert_eq!(imported.mirror_statuses, 2);
+
+    let workers = db.list_workers().unwrap();
+    assert_eq!(workers.len(), 1);
+    assert_eq!(workers[0].token, "secret");
+    assert_eq!(
+        workers[0].last_online,
+        Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+    );
+    let jobs = db.list_mirror_status("test_worker1").unwrap();
+    let names: Vec<_> = jobs.iter().map(|j| j.name.as_str()).collect();
+    assert_eq!(names, ["debian", "elvish"]);
+    assert_eq!(jobs[0].status, SyncStatus::Failed);
+    assert_eq!(jobs[0].error_msg, "rsync error");
+    assert_eq!(jobs[0].last_started, DateTime::<Utc>::default());
+    assert_eq!(jobs[1].error_msg.len(), 5000);
+    assert_eq!(
+        jobs[1].next_schedule,
+        Utc.with_ymd_and_hms(2024, 5, 1, 11, 0, 0).unwrap()
+    );
+
+    // importing again replaces the same records
+    import_bolt(&bolt_file, db.as_ref()).unwrap();
+    assert_eq!(db.list_all_mirror_status().unwrap().len(), 2);
+}
+
+#[test]
+fn damaged_bolt_db_should_import_nothing() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let bolt_file = tmp_dir.path().join("tunasync.db");
+    write_tunasync_db(&bolt_file);
+    let data = std::fs::read(&bolt_file).unwrap();
+    // the second status is lost
+    std::fs::write(&bolt_file, &data[..7 * PAGE_SIZE]).unwrap();
+    let db = make_db_adapter("memory", "").unwrap();
+    db.init().unwrap();
+
+    let err = import_bolt(&bolt_file, db.as_ref()).unwrap_err();
+    assert!(matches!(err, BoltError::Invalid(_)), "{}", err);
+    assert!(db.list_workers().unwrap().is_empty());
+
+    std::fs::write(&bolt_file, b"not a bolt file").unwrap();
+    assert!(import_bolt(&bolt_file, db.as_ref()).is_err());
+}
+
+#[test]
+fn failed_import_should_write_nothing() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let bolt_file = tmp_dir.path().join("tunasync.db");
+    write_tunasync_db(&bolt_file);
+    let db_file = tmp_dir.path().join("manager.sqlite");
+    let db = make_db_adapter("sqlite", db_file.to_string_lossy()).unwrap();
+    db.init().unwrap();
+    // the write of the last status fails, after the worker and the first status
+    rusqlite::Connection::open(&db_file)
+        .unwrap()
+        .execute_batch(
+            "CREATE TRIGGER fail_elvish BEFORE INSERT ON mirror_status
+             WHEN NEW.name = 'elvish'
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+
+    let err = import_bolt(&bolt_file, db.as_ref()).unwrap_err();
+    assert!(matches!(err, BoltError::Adapter(_)), "{}", err);
+    assert!(db.list_workers().unwrap().is_empty());
+    assert!(db.list_all_mirror_status().unwrap().is_empty());
+}
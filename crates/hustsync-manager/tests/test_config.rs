@@ -1,4 +1,5 @@
 #![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
 
 use hustsync_config_parser::ManagerConfig;
 use std::fs;
@@ -0,0 +1,461 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use hustsync_config_parser::{ManagerConfig, ManagerFileConfig, ManagerServerConfig};
+use hustsync_internal::msg::{MirrorStatus, WorkerStatus};
+use hustsync_internal::status::SyncStatus;
+use hustsync_manager::database::{AdapterError, DbAdapterTrait, make_db_adapter};
+use hustsync_manager::get_hustsync_manager;
+use reqwest::StatusCode;
+
+type HashMaps = HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>;
+
+#[derive(Default)]
+struct Store {
+    hashes: HashMaps,
+    sorted_sets: HashMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    // how many times each key was written, for WATCH to tell
+    versions: HashMap<Vec<u8>, u64>,
+}
+
+type Shared = Arc<Mutex<Store>>;
+
+/// Holds up the next EXEC of a transaction that watched keys, for a test to
+/// write something in between.
+#[derive(Default)]
+struct ExecGate {
+    paused: Mutex<Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>>,
+}
+
+impl ExecGate {
+    /// Pause the next watched EXEC, returning the receiver told when it is
+    /// reached and the sender to let it go on.
+    fn pause_next(&self) -> (mpsc::Receiver<()>, mpsc::Sender<()>) {
+        let (reached_tx, reached_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        *self.paused.lock().unwrap() = Some((reached_tx, release_rx));
+        (reached_rx, release_tx)
+    }
+
+    fn wait(&self) {
+        let paused = self.paused.lock().unwrap().take();
+        if let Some((reached, release)) = paused {
+            reached.send(()).unwrap();
+            release.recv().unwrap();
+        }
+    }
+}
+
+// just enough of a Redis server for the hash and sorted set commands the
+// manager sends
+fn start_fake_redis() -> String {
+    start_gated_fake_redis(Arc::default())
+}
+
+fn start_gated_fake_redis(gate: Arc<ExecGate>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store = Shared::default();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let store = Arc::clone(&store);
+            let gate = Arc::clone(&gate);
+            thread::spawn(move || serve_resp(stream, &store, &gate));
+        }
+    });
+    format!("redis://{}/0", addr)
+}
+
+fn serve_resp(stream: TcpStream, store: &Shared, gate: &ExecGate) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    // the commands queued since MULTI, applied together on EXEC
+    let mut queued: Option<Vec<Vec<Vec<u8>>>> = None;
+    // the keys watched, with their versions when they were
+    let mut watched: Vec<(Vec<u8>, u64)> = Vec::new();
+    while let Some(args) = read_command(&mut reader)? {
+        let cmd = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let reply = match (cmd.as_str(), queued.as_mut()) {
+            ("WATCH", None) => {
+                let store = store.lock().unwrap();
+                for key in &args[1..] {
+                    let version = store.versions.get(key).copied().unwrap_or_default();
+                    watched.push((key.clone(), version));
+                }
+                b"+OK\r\n".to_vec()
+            }
+            ("UNWATCH", None) => {
+                watched.clear();
+                b"+OK\r\n".to_vec()
+            }
+            ("MULTI", None) => {
+                queued = Some(Vec::new());
+                b"+OK\r\n".to_vec()
+            }
+            ("EXEC", Some(_)) => {
+                if !watched.is_empty() {
+                    gate.wait();
+                }
+                let cmds = queued.take().unwrap_or_default();
+                let mut store = store.lock().unwrap();
+                let changed = watched.drain(..).any(|(key, version)| {
+                    store.versions.get(&key).copied().unwrap_or_default() != version
+                });
+                if changed {
+                    b"*-1\r\n".to_vec()
+                } else {
+                    let mut reply = format!("*{}\r\n", cmds.len()).into_bytes();
+                    for args in &cmds {
+                        reply.extend(apply_command(args, &mut store));
+                    }
+                    reply
+                }
+            }
+            (_, Some(cmds)) => {
+                cmds.push(args);
+                b"+QUEUED\r\n".to_vec()
+            }
+            (_, None) => apply_command(&args, &mut store.lock().unwrap()),
+        };
+        writer.write_all(&reply)?;
+    }
+    Ok(())
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end().to_string()))
+}
+
+fn read_command(reader: &mut impl BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(header) = read_line(reader)? else {
+        return Ok(None);
+    };
+    let count: usize = header.trim_start_matches('*').parse().unwrap();
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(reader)?
+            .unwrap()
+            .trim_start_matches('$')
+            .parse()
+            .unwrap();
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg)?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+fn bulk(value: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", value.len()).into_bytes();
+    reply.extend_from_slice(value);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+fn array(items: impl ExactSizeIterator<Item = Option<Vec<u8>>>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        reply.extend(item.map_or_else(|| b"$-1\r\n".to_vec(), |v| bulk(&v)));
+    }
+    reply
+}
+
+// a bound of ZRANGEBYLEX, such as `[a`, `(a`, `-` or `+`
+fn lex_bound(arg: &[u8]) -> Bound<Vec<u8>> {
+    match arg.split_first() {
+        Some((b'[', rest)) => Bound::Included(rest.to_vec()),
+        Some((b'(', rest)) => Bound::Excluded(rest.to_vec()),
+        _ => Bound::Unbounded,
+    }
+}
+
+fn apply_command(args: &[Vec<u8>], store: &mut Store) -> Vec<u8> {
+    let cmd = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+    if matches!(cmd.as_str(), "HSET" | "HDEL" | "ZADD" | "ZREM")
+        && let Some(key) = args.get(1)
+    {
+        *store.versions.entry(key.clone()).or_default() += 1;
+    }
+    let Store {
+        hashes,
+        sorted_sets,
+        ..
+    } = store;
+    match (cmd.as_str(), args) {
+        ("HGET", [_, hash, field]) => hashes
+            .get(hash)
+            .and_then(|h| h.get(field))
+            .map_or_else(|| b"$-1\r\n".to_vec(), |v| bulk(v)),
+        ("HMGET", [_, hash, fields @ ..]) => {
+            let h = hashes.get(hash);
+            array(fields.iter().map(|f| h.and_then(|h| h.get(f)).cloned()))
+        }
+        ("HSET", [_, hash, field, value]) => {
+            let added = hashes
+                .entry(hash.clone())
+                .or_default()
+                .insert(field.clone(), value.clone())
+                .is_none();
+            format!(":{}\r\n", u8::from(added)).into_bytes()
+        }
+        ("HDEL", [_, hash, field]) => {
+            let removed = hashes.get_mut(hash).and_then(|h| h.remove(field));
+            format!(":{}\r\n", u8::from(removed.is_some())).into_bytes()
+        }
+        ("HGETALL", [_, hash]) => {
+            let fields = hashes.get(hash).cloned().unwrap_or_default();
+            let mut reply = format!("*{}\r\n", fields.len() * 2).into_bytes();
+            for (field, value) in &fields {
+                reply.extend(bulk(field));
+                reply.extend(bulk(value));
+            }
+            reply
+        }
+        ("HKEYS", [_, hash]) => {
+            let fields = hashes.get(hash).cloned().unwrap_or_default();
+            array(fields.into_keys().map(Some))
+        }
+        ("EXISTS", [_, key]) => {
+            let exists =
+                hashes.contains_key(key) || sorted_sets.get(key).is_some_and(|s| !s.is_empty());
+            format!(":{}\r\n", u8::from(exists)).into_bytes()
+        }
+        // the scores are all the same, so members are kept in lexical order
+        ("ZADD", [_, key, members @ ..]) => {
+            let set = sorted_sets.entry(key.clone()).or_default();
+            let added = members
+                .chunks(2)
+                .filter(|pair| set.insert(pair[1].clone()))
+                .count();
+            format!(":{}\r\n", added).into_bytes()
+        }
+        ("ZREM", [_, key, member]) => {
+            let removed = sorted_sets.get_mut(key).is_some_and(|s| s.remove(member));
+            format!(":{}\r\n", u8::from(removed)).into_bytes()
+        }
+        ("ZRANGEBYLEX", [_, key, min, max]) => {
+            let members: Vec<_> = sorted_sets
+                .get(key)
+                .into_iter()
+                .flat_map(|s| s.range((lex_bound(min), lex_bound(max))))
+                .map(|m| Some(m.clone()))
+                .collect();
+            array(members.into_iter())
+        }
+        _ => format!("-ERR unknown command '{}'\r\n", cmd).into_bytes(),
+    }
+}
+
+fn redis_manager_config(url: &str) -> ManagerConfig {
+    ManagerConfig {
+        server: Some(ManagerServerConfig {
+            addr: Some("127.0.0.1".into()),
+            port: Some(0),
+            ssl_cert: None,
+            ssl_key: None,
+            require_worker_token: None,
+        }),
+        files: Some(ManagerFileConfig {
+            status_file: None,
+            db_type: Some("redis".into()),
+            db_file: Some(url.to_string()),
+            ca_cert: None,
+        }),
+        debug: Some(false),
+    }
+}
+
+async fn serve_redis_manager(url: &str) -> String {
+    let manager = get_hustsync_manager(redis_manager_config(url)).expect("create manager");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(manager.serve(listener));
+    format!("http://{}", addr)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn managers_should_share_state_through_redis() {
+    let url = start_fake_redis();
+    let first = serve_redis_manager(&url).await;
+    let second = serve_redis_manager(&url).await;
+    let client = reqwest::Client::new();
+
+    let registered: WorkerStatus = client
+        .post(format!("{}/workers", first))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // the status reported through one manager is seen by the other
+    let resp = client
+        .post(format!("{}/workers/test_worker1/jobs/elvish", second))
+        .bearer_auth(&registered.token)
+        .json(&MirrorStatus {
+            name: "elvish".into(),
+            worker: "test_worker1".into(),
+            status: SyncStatus::Success,
+            ..MirrorStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", first))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].status, SyncStatus::Success);
+
+    let resp = client
+        .delete(format!("{}/workers/test_worker1", second))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let workers: Vec<WorkerStatus> = reqwest::get(format!("{}/workers", first))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(workers.is_empty());
+}
+
+#[test]
+fn unreachable_redis_should_fail_startup() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}/0", listener.local_addr().unwrap());
+    drop(listener);
+    assert!(get_hustsync_manager(redis_manager_config(&url)).is_err());
+}
+
+fn open_redis_adapter(url: &str) -> Box<dyn DbAdapterTrait> {
+    let db = make_db_adapter("redis", url).unwrap();
+    db.init().unwrap();
+    db
+}
+
+#[test]
+fn refresh_should_not_bring_back_a_worker_deleted_through_another_adapter() {
+    let gate = Arc::new(ExecGate::default());
+    let url = start_gated_fake_redis(Arc::clone(&gate));
+    let first = open_redis_adapter(&url);
+    let second = open_redis_adapter(&url);
+    first
+        .create_worker(WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .unwrap();
+
+    let (reached, release) = gate.pause_next();
+    let refreshing = thread::spawn(move || first.refresh_worker("test_worker1"));
+    // the refresh has read the worker, and is about to write it back
+    reached.recv().unwrap();
+    second.delete_worker("test_worker1", false).unwrap();
+    release.send(()).unwrap();
+
+    let refreshed = refreshing.join().unwrap();
+    assert!(matches!(refreshed, Err(AdapterError::WorkerNotFound(_))));
+    assert!(second.list_workers().unwrap().is_empty());
+}
+
+#[test]
+fn deletion_should_take_statuses_written_through_another_adapter() {
+    let gate = Arc::new(ExecGate::default());
+    let url = start_gated_fake_redis(Arc::clone(&gate));
+    let first = open_redis_adapter(&url);
+    let second = open_redis_adapter(&url);
+    first
+        .create_worker(WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .unwrap();
+    let status = |name: &str| MirrorStatus {
+        name: name.into(),
+        worker: "test_worker1".into(),
+        status: SyncStatus::Success,
+        ..MirrorStatus::default()
+    };
+    first
+        .update_mirror_status("test_worker1", "elvish", status("elvish"))
+        .unwrap();
+
+    let (reached, release) = gate.pause_next();
+    let deleting = thread::spawn(move || first.delete_worker("test_worker1", false));
+    // the deletion has scanned the statuses of the worker, and is about to
+    // delete them
+    reached.recv().unwrap();
+    second
+        .update_mirror_status("test_worker1", "fish", status("fish"))
+        .unwrap();
+    release.send(()).unwrap();
+
+    deleting.join().unwrap().unwrap();
+    assert!(second.list_all_mirror_status().unwrap().is_empty());
+    assert!(
+        second
+            .list_mirror_status("test_worker1")
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn statuses_stored_before_the_sorted_keys_should_be_listed() {
+    let url = start_fake_redis();
+    {
+        // the hashes as written before their fields were kept in sorted sets
+        let mut conn = redis::Client::open(url.as_str())
+            .unwrap()
+            .get_connection()
+            .unwrap();
+        let status = serde_json::to_vec(&MirrorStatus {
+            name: "elvish".into(),
+            worker: "test_worker1".into(),
+            status: SyncStatus::Success,
+            ..MirrorStatus::default()
+        })
+        .unwrap();
+        let mut pipe = redis::pipe();
+        pipe.hset("meta", "schema_version", "3")
+            .hset("mirror_status", "elvish/test_worker1", status)
+            .hset(
+                "mirror_status_by_worker",
+                "12:test_worker1/elvish",
+                "elvish/test_worker1",
+            );
+        pipe.query::<()>(&mut conn).unwrap();
+    }
+
+    let db = open_redis_adapter(&url);
+    let statuses = db.list_mirror_status("test_worker1").unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].name, "elvish");
+    assert!(db.list_mirror_status("test_worker").unwrap().is_empty());
+}
@@ -0,0 +1,996 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{TimeZone, Utc};
+use hustsync_config_parser::{ManagerConfig, ManagerFileConfig, ManagerServerConfig};
+use hustsync_internal::msg::{CmdVerb, MirrorStatus, WorkerCmd, WorkerStatus};
+use hustsync_internal::status::SyncStatus;
+use hustsync_manager::get_hustsync_manager;
+use redb::ReadableTable;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::path::Path;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+
+// a manager keeping its db in memory, for tests that need no files
+fn memory_manager_config() -> ManagerConfig {
+    ManagerConfig {
+        server: Some(ManagerServerConfig {
+            addr: Some("127.0.0.1".into()),
+            port: Some(0),
+            ssl_cert: None,
+            ssl_key: None,
+            require_worker_token: None,
+        }),
+        files: Some(ManagerFileConfig {
+            status_file: None,
+            db_type: Some("memory".into()),
+            db_file: None,
+            ca_cert: None,
+        }),
+        debug: Some(false),
+    }
+}
+
+fn test_manager_config(tmp_dir: &TempDir) -> ManagerConfig {
+    let mut config = memory_manager_config();
+    if let Some(files) = config.files.as_mut() {
+        let db_file = tmp_dir.path().join("manager.db");
+        files.db_type = Some("redb".into());
+        files.db_file = Some(db_file.to_string_lossy().into_owned());
+    }
+    config
+}
+
+async fn serve_test_manager(config: ManagerConfig) -> SocketAddr {
+    let manager = get_hustsync_manager(config).expect("create manager");
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(manager.serve(listener));
+    addr
+}
+
+async fn start_test_manager() -> String {
+    format!(
+        "http://{}",
+        serve_test_manager(memory_manager_config()).await
+    )
+}
+
+#[tokio::test]
+async fn ping_should_pong() {
+    let base_url = start_test_manager().await;
+    let resp = reqwest::get(format!("{}/ping", base_url)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["message"], "pong");
+}
+
+#[tokio::test]
+async fn empty_manager_should_list_nothing() {
+    let base_url = start_test_manager().await;
+
+    let jobs: Vec<Value> = reqwest::get(format!("{}/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(jobs.is_empty());
+
+    let workers: Vec<Value> = reqwest::get(format!("{}/workers", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(workers.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_worker_should_be_rejected() {
+    let base_url = start_test_manager().await;
+    let resp = reqwest::get(format!("{}/workers/test_worker/jobs", base_url))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid workerID test_worker");
+    assert_eq!(body["code"], "worker_not_found");
+}
+
+#[tokio::test]
+async fn unknown_mirror_should_be_not_found() {
+    let base_url = start_test_manager().await;
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = client
+        .post(format!(
+            "{}/workers/test_worker1/jobs/elvish/size",
+            base_url
+        ))
+        .json(&json!({ "name": "elvish", "size": "1.33T" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "mirror_not_found");
+    assert_eq!(
+        body["error"],
+        "failed to update size of mirror elvish @<test_worker1>: \
+         no mirror 'elvish' exists in worker 'test_worker1'"
+    );
+}
+
+#[tokio::test]
+async fn flush_disabled_jobs_should_work() {
+    let base_url = start_test_manager().await;
+    let resp = reqwest::Client::new()
+        .delete(format!("{}/jobs/disabled", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["message"], "flushed");
+}
+
+#[tokio::test]
+async fn flush_should_drop_disabled_jobs_only() {
+    let base_url = start_test_manager().await;
+    let client = reqwest::Client::new();
+    let registered: WorkerStatus = client
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    for (name, status) in [
+        ("elvish", SyncStatus::Success),
+        ("debian", SyncStatus::Disabled),
+        ("ubuntu", SyncStatus::Disabled),
+    ] {
+        let resp = client
+            .post(format!("{}/workers/test_worker1/jobs/{}", base_url, name))
+            .bearer_auth(&registered.token)
+            .json(&MirrorStatus {
+                name: name.into(),
+                worker: "test_worker1".into(),
+                status,
+                ..MirrorStatus::default()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let resp = client
+        .delete(format!("{}/jobs/disabled", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].name, "elvish");
+    let jobs: Vec<Value> = reqwest::get(format!("{}/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+}
+
+#[tokio::test]
+async fn deleting_a_worker_should_drop_its_jobs() {
+    let base_url = start_test_manager().await;
+    let client = reqwest::Client::new();
+    for worker_id in ["test_worker1", "test_worker2"] {
+        let registered: WorkerStatus = client
+            .post(format!("{}/workers", base_url))
+            .json(&WorkerStatus {
+                id: worker_id.into(),
+                ..WorkerStatus::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let resp = client
+            .post(format!("{}/workers/{}/jobs/elvish", base_url, worker_id))
+            .bearer_auth(&registered.token)
+            .json(&MirrorStatus {
+                name: "elvish".into(),
+                worker: worker_id.into(),
+                status: SyncStatus::Success,
+                ..MirrorStatus::default()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let resp = client
+        .delete(format!("{}/workers/test_worker1", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let jobs: Vec<Value> = reqwest::get(format!("{}/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+
+    // archived statuses are off the status page all the same
+    let resp = client
+        .delete(format!(
+            "{}/workers/test_worker2?keep_history=true",
+            base_url
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let jobs: Vec<Value> = reqwest::get(format!("{}/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(jobs.is_empty());
+}
+
+#[tokio::test]
+async fn register_and_heartbeat_worker_should_work() {
+    let base_url = start_test_manager().await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("{}/workers", base_url))
+        .json(&json!({
+            "id": "test_worker1",
+            "url": "http://127.0.0.1:6000/",
+            "token": "secret",
+            "last_online": "2016-04-16T23:08:10Z",
+            "last_register": "2016-04-16T23:08:10Z",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let registered: WorkerStatus = resp.json().await.unwrap();
+    assert_eq!(registered.id, "test_worker1");
+    assert!(registered.last_register > Utc.with_ymd_and_hms(2016, 4, 17, 0, 0, 0).unwrap());
+    assert_eq!(registered.last_online, registered.last_register);
+
+    let workers: Vec<WorkerStatus> = reqwest::get(format!("{}/workers", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(workers.len(), 1);
+    assert_eq!(workers[0].id, "test_worker1");
+    assert_eq!(workers[0].url, "http://127.0.0.1:6000/");
+    assert!(workers[0].token.is_empty());
+
+    let resp = client
+        .post(format!("{}/workers/test_worker1/heartbeat", base_url))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let refreshed: WorkerStatus = resp.json().await.unwrap();
+    assert!(refreshed.last_online >= registered.last_online);
+    assert_eq!(refreshed.last_register, registered.last_register);
+}
+
+#[tokio::test]
+async fn worker_calls_should_require_the_worker_token() {
+    let mut config = memory_manager_config();
+    if let Some(server) = config.server.as_mut() {
+        server.require_worker_token = Some(true);
+    }
+    let base_url = format!("http://{}", serve_test_manager(config).await);
+    let client = reqwest::Client::new();
+    let worker = WorkerStatus {
+        id: "test_worker1".into(),
+        ..WorkerStatus::default()
+    };
+
+    // a worker that brings no token is issued one
+    let registered: WorkerStatus = client
+        .post(format!("{}/workers", base_url))
+        .json(&worker)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(registered.token.len(), 64);
+
+    let heartbeat_url = format!("{}/workers/test_worker1/heartbeat", base_url);
+    let resp = client.post(&heartbeat_url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = client
+        .post(&heartbeat_url)
+        .bearer_auth("not the token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid token for worker test_worker1");
+    let resp = client
+        .post(&heartbeat_url)
+        .bearer_auth(&registered.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // nobody else can take over the worker by registering under its name
+    let resp = client
+        .post(format!("{}/workers", base_url))
+        .json(&worker)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = client
+        .post(format!("{}/workers", base_url))
+        .bearer_auth(&registered.token)
+        .json(&worker)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // rotating the token keeps the statuses of the worker
+    let resp = client
+        .post(format!("{}/workers/test_worker1/jobs/elvish", base_url))
+        .bearer_auth(&registered.token)
+        .json(&MirrorStatus {
+            name: "elvish".into(),
+            worker: "test_worker1".into(),
+            status: SyncStatus::Success,
+            ..MirrorStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let rotated: WorkerStatus = client
+        .post(format!("{}/workers/test_worker1/token", base_url))
+        .bearer_auth(&registered.token)
+        .json(&json!({ "token": "rotated" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(rotated.token, "rotated");
+    let resp = client
+        .post(&heartbeat_url)
+        .bearer_auth(&registered.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = client
+        .post(&heartbeat_url)
+        .bearer_auth("rotated")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].status, SyncStatus::Success);
+
+    // ctl sets sizes without the worker token, as it does not have one
+    let resp = client
+        .post(format!(
+            "{}/workers/test_worker1/jobs/elvish/size",
+            base_url
+        ))
+        .json(&json!({ "name": "elvish", "size": "1.33T" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let status: MirrorStatus = resp.json().await.unwrap();
+    assert_eq!(status.size, "1.33T");
+}
+
+#[tokio::test]
+async fn tunasync_worker_without_token_should_report() {
+    let base_url = start_test_manager().await;
+    let client = reqwest::Client::new();
+    // as a Go tunasync worker registers, and registers again after a restart
+    let worker = json!({
+        "id": "test_worker1",
+        "url": "http://127.0.0.1:6000/",
+        "token": "",
+        "last_online": "0001-01-01T00:00:00Z",
+        "last_register": "0001-01-01T00:00:00Z",
+    });
+    for _ in 0..2 {
+        let resp = client
+            .post(format!("{}/workers", base_url))
+            .json(&worker)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let resp = client
+        .post(format!("{}/workers/test_worker1/jobs/elvish", base_url))
+        .json(&MirrorStatus {
+            name: "elvish".into(),
+            worker: "test_worker1".into(),
+            status: SyncStatus::Success,
+            ..MirrorStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = client
+        .post(format!("{}/workers/test_worker1/heartbeat", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // a wrong token is still turned away
+    let resp = client
+        .post(format!("{}/workers/test_worker1/heartbeat", base_url))
+        .bearer_auth("not the token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn jobs_should_be_listed_by_worker() {
+    let base_url = start_test_manager().await;
+    let client = reqwest::Client::new();
+
+    // one worker ID is a prefix of the other, and a mirror name holds a '/'
+    for (worker_id, mirrors) in [
+        ("test_worker", &["elvish"][..]),
+        ("test_worker1", &["elvish", "ubuntu/ports"][..]),
+    ] {
+        let registered: WorkerStatus = client
+            .post(format!("{}/workers", base_url))
+            .json(&WorkerStatus {
+                id: worker_id.into(),
+                ..WorkerStatus::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        for name in mirrors {
+            let resp = client
+                .post(format!("{}/workers/{}/jobs/mirror", base_url, worker_id))
+                .bearer_auth(&registered.token)
+                .json(&MirrorStatus {
+                    name: name.to_string(),
+                    worker: worker_id.into(),
+                    ..MirrorStatus::default()
+                })
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+    }
+
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let names: Vec<_> = jobs.iter().map(|j| j.name.as_str()).collect();
+    assert_eq!(names, ["elvish", "ubuntu/ports"]);
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].worker, "test_worker");
+}
+
+#[tokio::test]
+async fn register_worker_without_id_should_fail() {
+    let base_url = start_test_manager().await;
+    let resp = reqwest::Client::new()
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus::default())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+// a worker stand-in that accepts any command and hands it over
+async fn start_fake_worker() -> (String, mpsc::UnboundedReceiver<WorkerCmd>) {
+    let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let app = Router::new().route(
+        "/",
+        post(move |Json(cmd): Json<WorkerCmd>| async move {
+            let _ = cmd_tx.send(cmd);
+            Json(json!({ "message": "ok" }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (format!("http://{}/", addr), cmd_rx)
+}
+
+#[tokio::test]
+async fn client_cmd_should_be_relayed_to_worker() {
+    let base_url = start_test_manager().await;
+    let (worker_url, mut cmd_rx) = start_fake_worker().await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            url: worker_url,
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = client
+        .post(format!("{}/cmd", base_url))
+        .json(&json!({
+            "cmd": "disable",
+            "worker_id": "test_worker1",
+            "mirror_id": "elvish",
+            "args": [],
+            "options": { "force": true },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(
+        body["message"],
+        "successfully send command to worker test_worker1"
+    );
+
+    let cmd = cmd_rx.recv().await.unwrap();
+    assert_eq!(cmd.cmd, CmdVerb::Disable);
+    assert_eq!(cmd.mirror_id, "elvish");
+    assert_eq!(cmd.options.get("force"), Some(&true));
+
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].name, "elvish");
+    assert_eq!(jobs[0].status, SyncStatus::Disabled);
+}
+
+#[tokio::test]
+async fn client_cmd_to_unknown_worker_should_fail() {
+    let base_url = start_test_manager().await;
+    let resp = reqwest::Client::new()
+        .post(format!("{}/cmd", base_url))
+        .json(&json!({
+            "cmd": "start",
+            "worker_id": "no_such_worker",
+            "mirror_id": "elvish",
+            "args": [],
+            "options": {},
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "worker no_such_worker is not registered yet");
+}
+
+#[tokio::test]
+async fn status_file_should_follow_job_updates() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let status_file = tmp_dir.path().join("status.json");
+    let mut config = test_manager_config(&tmp_dir);
+    if let Some(files) = config.files.as_mut() {
+        files.status_file = Some(status_file.to_string_lossy().into_owned());
+    }
+    let base_url = format!("http://{}", serve_test_manager(config).await);
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let registered: WorkerStatus = resp.json().await.unwrap();
+
+    let resp = client
+        .post(format!("{}/workers/test_worker1/jobs/elvish", base_url))
+        .bearer_auth(&registered.token)
+        .json(&MirrorStatus {
+            name: "elvish".into(),
+            worker: "test_worker1".into(),
+            upstream: "rsync://rsync.elv.sh/elvish/".into(),
+            size: "1.33T".into(),
+            status: SyncStatus::Success,
+            ..MirrorStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let updated: MirrorStatus = resp.json().await.unwrap();
+
+    let statuses: Vec<Value> =
+        serde_json::from_slice(&std::fs::read(&status_file).unwrap()).unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0]["name"], "elvish");
+    assert_eq!(statuses[0]["status"], "success");
+    assert_eq!(statuses[0]["size"], "1.33T");
+    assert_eq!(
+        statuses[0]["last_update_ts"],
+        updated.last_update.timestamp()
+    );
+    assert!(statuses[0]["last_update"].is_string());
+    assert!(statuses[0]["next_schedule_ts"].is_i64());
+    assert!(!tmp_dir.path().join("status.json.tmp").exists());
+}
+
+// a certificate for localhost, returned as PEM after being written to `dir`
+fn write_self_signed(dir: &Path) -> String {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), certified.signing_key.serialize_pem()).unwrap();
+    certified.cert.pem()
+}
+
+fn client_trusting(cert_pem: &str) -> reqwest::Client {
+    let cert = reqwest::Certificate::from_pem(cert_pem.as_bytes()).unwrap();
+    reqwest::Client::builder()
+        .add_root_certificate(cert)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn manager_should_serve_https_and_reload_cert_on_sighup() {
+    // handle SIGHUP in this process before sending it, instead of being killed by it
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let old_cert = write_self_signed(tmp_dir.path());
+    let mut config = test_manager_config(&tmp_dir);
+    if let Some(server) = config.server.as_mut() {
+        server.ssl_cert = Some(
+            tmp_dir
+                .path()
+                .join("cert.pem")
+                .to_string_lossy()
+                .into_owned(),
+        );
+        server.ssl_key = Some(
+            tmp_dir
+                .path()
+                .join("key.pem")
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+    let addr = serve_test_manager(config).await;
+    let ping_url = format!("https://localhost:{}/ping", addr.port());
+
+    let old_client = client_trusting(&old_cert);
+    let resp = old_client.get(&ping_url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(reqwest::get(format!("http://{}/ping", addr)).await.is_err());
+
+    let new_cert = write_self_signed(tmp_dir.path());
+    nix::sys::signal::raise(nix::sys::signal::Signal::SIGHUP).unwrap();
+    hangup.recv().await;
+    let new_client = client_trusting(&new_cert);
+    let mut reloaded = false;
+    for _ in 0..50 {
+        if new_client.get(&ping_url).send().await.is_ok() {
+            reloaded = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(reloaded);
+
+    // the connection opened before the reload is kept, new ones get the new cert
+    let resp = old_client.get(&ping_url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(
+        client_trusting(&old_cert)
+            .get(&ping_url)
+            .send()
+            .await
+            .is_err()
+    );
+}
+
+#[test]
+fn half_a_cert_pair_should_be_refused() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut config = test_manager_config(&tmp_dir);
+    if let Some(server) = config.server.as_mut() {
+        server.ssl_cert = Some("/etc/hustsync/cert.pem".into());
+    }
+    let err = get_hustsync_manager(config).err().unwrap();
+    assert!(err.to_string().contains("ssl_key"));
+}
+
+struct TestPki {
+    ca: rcgen::CertifiedIssuer<'static, rcgen::KeyPair>,
+}
+
+impl TestPki {
+    fn new() -> Self {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "hustsync test CA");
+        let key = rcgen::KeyPair::generate().unwrap();
+        TestPki {
+            ca: rcgen::CertifiedIssuer::self_signed(params, key).unwrap(),
+        }
+    }
+
+    // a certificate for `name` signed by the CA, as (cert, key) PEM
+    fn issue(&self, name: &str) -> (String, String) {
+        let params = rcgen::CertificateParams::new(vec![name.to_string()]).unwrap();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.ca).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+}
+
+fn client_with_identity(ca_pem: &str, identity: Option<(String, String)>) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(ca_pem.as_bytes()).unwrap());
+    if let Some((cert, key)) = identity {
+        let identity = reqwest::Identity::from_pem(format!("{}{}", cert, key).as_bytes()).unwrap();
+        builder = builder.identity(identity);
+    }
+    builder.build().unwrap()
+}
+
+#[tokio::test]
+async fn manager_should_verify_worker_client_certs() {
+    let pki = TestPki::new();
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let (cert, key) = pki.issue("localhost");
+    let file = |name: &str, content: &str| {
+        let path = tmp_dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        Some(path.to_string_lossy().into_owned())
+    };
+    let mut config = test_manager_config(&tmp_dir);
+    if let Some(server) = config.server.as_mut() {
+        server.ssl_cert = file("cert.pem", &cert);
+        server.ssl_key = file("key.pem", &key);
+    }
+    if let Some(files) = config.files.as_mut() {
+        files.ca_cert = file("ca.pem", &pki.ca.pem());
+    }
+    let addr = serve_test_manager(config).await;
+    let base_url = format!("https://localhost:{}", addr.port());
+    let worker = WorkerStatus {
+        id: "test_worker1".into(),
+        ..WorkerStatus::default()
+    };
+
+    // reads stay open to clients without a certificate, such as ctl
+    let anonymous = client_with_identity(&pki.ca.pem(), None);
+    let resp = anonymous
+        .get(format!("{}/jobs", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = anonymous
+        .post(format!("{}/workers", base_url))
+        .json(&worker)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let trusted = client_with_identity(&pki.ca.pem(), Some(pki.issue("test_worker1")));
+    let resp = trusted
+        .post(format!("{}/workers", base_url))
+        .json(&worker)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let registered: WorkerStatus = resp.json().await.unwrap();
+    let resp = trusted
+        .post(format!("{}/workers/test_worker1/heartbeat", base_url))
+        .bearer_auth(&registered.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = anonymous
+        .post(format!("{}/workers/test_worker1/heartbeat", base_url))
+        .bearer_auth(&registered.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // a certificate from another CA fails the handshake
+    let rogue = client_with_identity(&pki.ca.pem(), Some(TestPki::new().issue("test_worker1")));
+    assert!(
+        rogue
+            .post(format!("{}/workers", base_url))
+            .json(&worker)
+            .send()
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn statuses_stored_before_the_worker_index_should_be_listed() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let config = test_manager_config(&tmp_dir);
+    {
+        // a db as written before statuses were indexed by worker, with
+        // the fields named in kebab-case
+        let db_file = tmp_dir.path().join("manager.db");
+        let db = redb::Database::create(&db_file).unwrap();
+        let table: redb::TableDefinition<&str, &[u8]> = redb::TableDefinition::new("mirror_status");
+        let status = serde_json::to_vec(&json!({
+            "name": "elvish",
+            "worker": "test_worker1",
+            "upstream": "rsync://rsync.elv.sh/elvish/",
+            "size": "1.33T",
+            "error-msg": "rsync error",
+            "last-update": "2024-05-01T10:00:00Z",
+            "last-started": "2024-05-01T09:59:00Z",
+            "last-ended": "2024-05-01T10:00:00Z",
+            "next-scheduled": "2024-05-01T11:00:00Z",
+            "status": "failed",
+            "is-master": true,
+        }))
+        .unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table)
+            .unwrap()
+            .insert("elvish/test_worker1", status.as_slice())
+            .unwrap();
+        txn.commit().unwrap();
+    }
+    let base_url = format!("http://{}", serve_test_manager(config).await);
+    let resp = reqwest::Client::new()
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].name, "elvish");
+    assert_eq!(jobs[0].error_msg, "rsync error");
+    assert_eq!(
+        jobs[0].next_schedule,
+        Utc.with_ymd_and_hms(2024, 5, 1, 11, 0, 0).unwrap()
+    );
+    assert!(jobs[0].is_master);
+}
+
+#[test]
+fn database_should_record_its_schema_version() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let db_file = tmp_dir.path().join("manager.db");
+    let meta: redb::TableDefinition<&str, &[u8]> = redb::TableDefinition::new("meta");
+    drop(get_hustsync_manager(test_manager_config(&tmp_dir)).unwrap());
+    {
+        let db = redb::Database::create(&db_file).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(meta).unwrap();
+            let version = table.get("schema_version").unwrap().unwrap();
+            assert_eq!(version.value(), b"3");
+            drop(version);
+            // as left by a newer manager
+            table.insert("schema_version", b"99".as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+    let err = get_hustsync_manager(test_manager_config(&tmp_dir))
+        .err()
+        .unwrap();
+    assert!(
+        err.to_string().contains("newer than the supported"),
+        "{}",
+        err
+    );
+}
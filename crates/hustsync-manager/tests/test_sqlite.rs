@@ -0,0 +1,217 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::path::Path;
+
+use hustsync_config_parser::{ManagerConfig, ManagerFileConfig, ManagerServerConfig};
+use hustsync_internal::msg::{MirrorStatus, WorkerStatus};
+use hustsync_internal::status::SyncStatus;
+use hustsync_manager::database::make_db_adapter;
+use hustsync_manager::get_hustsync_manager;
+use reqwest::StatusCode;
+use tokio::net::TcpListener;
+
+fn sqlite_manager_config(db_file: &Path) -> ManagerConfig {
+    ManagerConfig {
+        server: Some(ManagerServerConfig {
+            addr: Some("127.0.0.1".into()),
+            port: Some(0),
+            ssl_cert: None,
+            ssl_key: None,
+            require_worker_token: None,
+        }),
+        files: Some(ManagerFileConfig {
+            status_file: None,
+            db_type: Some("sqlite".into()),
+            db_file: Some(db_file.to_string_lossy().into_owned()),
+            ca_cert: None,
+        }),
+        debug: Some(false),
+    }
+}
+
+async fn serve_sqlite_manager(db_file: &Path) -> String {
+    let manager = get_hustsync_manager(sqlite_manager_config(db_file)).expect("create manager");
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(manager.serve(listener));
+    format!("http://{}", addr)
+}
+
+async fn report(base_url: &str, token: &str, name: &str, status: SyncStatus) {
+    let resp = reqwest::Client::new()
+        .post(format!("{}/workers/test_worker1/jobs/{}", base_url, name))
+        .bearer_auth(token)
+        .json(&MirrorStatus {
+            name: name.into(),
+            worker: "test_worker1".into(),
+            upstream: format!("rsync://example.com/{}/", name),
+            status,
+            ..MirrorStatus::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn sqlite_manager_should_keep_statuses() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let db_file = tmp_dir.path().join("manager.sqlite");
+    let base_url = serve_sqlite_manager(&db_file).await;
+
+    let registered: WorkerStatus = reqwest::Client::new()
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            url: "http://127.0.0.1:6000/".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    report(&base_url, &registered.token, "elvish", SyncStatus::Success).await;
+    report(
+        &base_url,
+        &registered.token,
+        "debian",
+        SyncStatus::PreSyncing,
+    )
+    .await;
+    report(&base_url, &registered.token, "old", SyncStatus::Disabled).await;
+
+    let resp = reqwest::Client::new()
+        .delete(format!("{}/jobs/disabled", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // a manager opening the same file sees what the first one stored
+    let base_url = serve_sqlite_manager(&db_file).await;
+    let jobs: Vec<MirrorStatus> = reqwest::get(format!("{}/workers/test_worker1/jobs", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let names: Vec<_> = jobs.iter().map(|j| j.name.as_str()).collect();
+    assert_eq!(names, ["debian", "elvish"]);
+    assert_eq!(jobs[0].status, SyncStatus::PreSyncing);
+    assert_eq!(jobs[0].upstream, "rsync://example.com/debian/");
+    let workers: Vec<WorkerStatus> = reqwest::get(format!("{}/workers", base_url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(workers.len(), 1);
+    assert_eq!(workers[0].url, "http://127.0.0.1:6000/");
+}
+
+#[tokio::test]
+async fn deleted_worker_history_should_be_archived() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let db_file = tmp_dir.path().join("manager.sqlite");
+    let base_url = serve_sqlite_manager(&db_file).await;
+    let client = reqwest::Client::new();
+
+    let registered: WorkerStatus = client
+        .post(format!("{}/workers", base_url))
+        .json(&WorkerStatus {
+            id: "test_worker1".into(),
+            ..WorkerStatus::default()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    report(&base_url, &registered.token, "elvish", SyncStatus::Success).await;
+    report(&base_url, &registered.token, "debian", SyncStatus::Failed).await;
+    let resp = client
+        .delete(format!(
+            "{}/workers/test_worker1?keep_history=true",
+            base_url
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let conn = rusqlite::Connection::open(&db_file).unwrap();
+    let count = |table: &str| -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+    assert_eq!(count("workers"), 0);
+    assert_eq!(count("mirror_status"), 0);
+    assert_eq!(count("mirror_status_archive"), 2);
+}
+
+#[test]
+fn sqlite_lookups_should_use_indexes() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let db_file = tmp_dir.path().join("manager.sqlite");
+    get_hustsync_manager(sqlite_manager_config(&db_file)).unwrap();
+
+    let conn = rusqlite::Connection::open(&db_file).unwrap();
+    for query in [
+        "SELECT * FROM mirror_status WHERE worker_id = 'w'",
+        "SELECT * FROM mirror_status WHERE name = 'elvish'",
+        "SELECT * FROM mirror_status WHERE status = 'failed'",
+    ] {
+        let plan: Vec<String> = conn
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", query))
+            .unwrap()
+            .query_map([], |row| row.get(3))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(
+            plan.iter().all(|step| step.contains("USING")),
+            "{}: {:?}",
+            query,
+            plan
+        );
+    }
+}
+
+#[test]
+fn sqlite_newer_schema_should_be_refused() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let db_file = tmp_dir.path().join("manager.sqlite");
+    drop(get_hustsync_manager(sqlite_manager_config(&db_file)).unwrap());
+
+    let conn = rusqlite::Connection::open(&db_file).unwrap();
+    let version: u32 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(version, 1);
+    conn.pragma_update(None, "user_version", 99).unwrap();
+    drop(conn);
+    assert!(get_hustsync_manager(sqlite_manager_config(&db_file)).is_err());
+}
+
+#[test]
+fn sqlite_unknown_status_should_be_an_error() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let db_file = tmp_dir.path().join("manager.sqlite");
+    let db = make_db_adapter("sqlite", db_file.to_string_lossy()).unwrap();
+    db.init().unwrap();
+    db.update_mirror_status("w", "elvish", MirrorStatus::default())
+        .unwrap();
+
+    let conn = rusqlite::Connection::open(&db_file).unwrap();
+    conn.execute("UPDATE mirror_status SET status = 'exploded'", [])
+        .unwrap();
+    assert!(db.get_mirror_status("w", "elvish").is_err());
+    assert!(db.list_all_mirror_status().is_err());
+}
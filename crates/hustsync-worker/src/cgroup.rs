@@ -0,0 +1,194 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use hustsync_config_parser::{MirrorConfig, WorkerCgroupConfig};
+use regex::Regex;
+use tracing::{debug, warn};
+
+use crate::provider::ProviderError;
+
+/// The cgroup (v2) a job runs its commands in, `base_path/group/<mirror>`.
+#[derive(Debug)]
+pub(crate) struct Cgroup {
+    path: PathBuf,
+    memory_limit: Option<u64>,
+}
+
+impl Cgroup {
+    /// The cgroup of `mirror`, if cgroups are enabled.
+    pub(crate) fn new(
+        config: &WorkerCgroupConfig,
+        mirror: &MirrorConfig,
+    ) -> Result<Option<Self>, ProviderError> {
+        if !config.enable.unwrap_or(false) {
+            return Ok(None);
+        }
+        let defaults = WorkerCgroupConfig::default();
+        let name = mirror.name.clone().unwrap_or_default();
+        let base_path = config
+            .base_path
+            .clone()
+            .or(defaults.base_path)
+            .unwrap_or_default();
+        let group = config.group.clone().or(defaults.group).unwrap_or_default();
+
+        let memory_limit = match mirror.memory_limit.as_deref() {
+            Some(limit) if !limit.is_empty() => {
+                Some(parse_memory_limit(limit).ok_or_else(|| {
+                    ProviderError::InvalidConfig(
+                        name.clone(),
+                        format!("bad memory limit '{}'", limit),
+                    )
+                })?)
+            }
+            _ => None,
+        };
+
+        Ok(Some(Cgroup {
+            path: PathBuf::from(base_path).join(group).join(name),
+            memory_limit,
+        }))
+    }
+
+    /// Create the cgroup and apply its limits, ahead of each sync.
+    pub(crate) fn create(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.path)?;
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        // the memory controller has to be enabled by the parent for memory.max to exist
+        if let Some(parent) = self.path.parent()
+            && let Err(e) = fs::write(parent.join("cgroup.subtree_control"), "+memory")
+        {
+            warn!(
+                "failed to enable the memory controller in {}: {}",
+                parent.display(),
+                e
+            );
+        }
+        fs::write(self.path.join("memory.max"), limit.to_string())
+    }
+
+    /// Open `cgroup.procs`, to which a process writes `0` to join the cgroup;
+    /// processes it spawns afterwards follow.
+    pub(crate) fn open_procs(&self) -> io::Result<File> {
+        debug!("joining cgroup {}", self.path.display());
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.path.join("cgroup.procs"))
+    }
+
+    /// Kill every process left in the cgroup.
+    pub(crate) fn kill(&self) -> io::Result<()> {
+        fs::write(self.path.join("cgroup.kill"), "1")
+    }
+}
+
+// a number with an optional unit, whose `i` only ever follows the unit letter
+static MEMORY_LIMIT_RE: LazyLock<Option<Regex>> =
+    LazyLock::new(|| Regex::new(r"^(\d+(?:\.\d+)?) ?(?:([kKmMgGtTpP])[iI]?)?[bB]?$").ok());
+
+/// Parse a human-readable memory size such as `512M` or `1.5GiB` into bytes, in
+/// binary units like docker does.
+fn parse_memory_limit(limit: &str) -> Option<u64> {
+    let caps = MEMORY_LIMIT_RE.as_ref()?.captures(limit.trim())?;
+    let value: f64 = caps.get(1)?.as_str().parse().ok()?;
+    let exp = match caps
+        .get(2)
+        .map(|m| m.as_str().to_ascii_uppercase())
+        .as_deref()
+    {
+        None => 0,
+        Some("K") => 1,
+        Some("M") => 2,
+        Some("G") => 3,
+        Some("T") => 4,
+        Some(_) => 5,
+    };
+    // truncating a fraction of a byte is fine
+    Some((value * 1024f64.powi(exp)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_cgroup(base_path: &str, memory_limit: Option<&str>) -> Option<Cgroup> {
+        let config = WorkerCgroupConfig {
+            enable: Some(true),
+            base_path: Some(base_path.into()),
+            group: Some("hustsync".into()),
+        };
+        let mirror = MirrorConfig {
+            memory_limit: memory_limit.map(String::from),
+            ..MirrorConfig::default()
+        };
+        Cgroup::new(&config, &mirror).unwrap()
+    }
+
+    #[test]
+    fn memory_limit_should_be_parsed() {
+        assert_eq!(parse_memory_limit("4096"), Some(4096));
+        assert_eq!(parse_memory_limit("512M"), Some(512 << 20));
+        assert_eq!(parse_memory_limit("512mb"), Some(512 << 20));
+        assert_eq!(parse_memory_limit("1.5GiB"), Some(3 << 29));
+        assert_eq!(parse_memory_limit("2 T"), Some(2 << 40));
+        assert_eq!(parse_memory_limit("lots"), None);
+        assert_eq!(parse_memory_limit("-1G"), None);
+    }
+
+    #[test]
+    fn memory_limit_without_unit_letter_should_be_rejected() {
+        assert_eq!(parse_memory_limit("512b"), Some(512));
+        assert_eq!(parse_memory_limit("512Mi"), Some(512 << 20));
+        assert_eq!(parse_memory_limit("512i"), None);
+        assert_eq!(parse_memory_limit("512ib"), None);
+        assert_eq!(parse_memory_limit("512X"), None);
+    }
+
+    #[test]
+    fn cgroup_should_be_disabled_by_default() {
+        let cgroup = Cgroup::new(&WorkerCgroupConfig::default(), &MirrorConfig::default());
+        assert!(cgroup.unwrap().is_none());
+    }
+
+    #[test]
+    fn cgroup_should_write_fake_cgroupfs() {
+        let fs_root = tempfile::tempdir().unwrap();
+        let cgroup = make_cgroup(&fs_root.path().to_string_lossy(), Some("512M")).unwrap();
+        let dir = fs_root.path().join("hustsync/elvish");
+        assert_eq!(cgroup.path, dir);
+
+        cgroup.create().unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("memory.max")).unwrap(),
+            "536870912"
+        );
+        assert_eq!(
+            fs::read_to_string(fs_root.path().join("hustsync/cgroup.subtree_control")).unwrap(),
+            "+memory"
+        );
+
+        io::Write::write_all(&mut cgroup.open_procs().unwrap(), b"0").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("cgroup.procs")).unwrap(), "0");
+        cgroup.kill().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("cgroup.kill")).unwrap(), "1");
+    }
+
+    #[test]
+    fn bad_memory_limit_should_be_rejected() {
+        let config = WorkerCgroupConfig {
+            enable: Some(true),
+            ..WorkerCgroupConfig::default()
+        };
+        let mirror = MirrorConfig {
+            memory_limit: Some("a lot".into()),
+            ..MirrorConfig::default()
+        };
+        assert!(Cgroup::new(&config, &mirror).is_err());
+    }
+}
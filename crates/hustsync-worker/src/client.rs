@@ -0,0 +1,270 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use hustsync_config_parser::WorkerManagerConfig;
+use hustsync_internal::msg::{MirrorSchedules, MirrorStatus, WorkerStatus};
+use hustsync_internal::util::{create_http_client_with_identity, get_json};
+use reqwest::blocking::Client;
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+use tracing::{debug, error, info, warn};
+
+const REGISTER_RETRIES: u32 = 10;
+const REGISTER_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("http client error: {0}")]
+    Http(String),
+    #[error("manager responded with {0}: {1}")]
+    Status(u16, String),
+    #[error("invalid response from manager: {0}")]
+    Decode(String),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+}
+
+/// Blocking client for the manager REST API.
+///
+/// The underlying `reqwest` client is blocking, so async callers must go through
+/// `tokio::task::spawn_blocking`.
+pub struct ManagerClient {
+    api_base: String,
+    client: Client,
+    // sent as bearer on the calls made for the worker, empty until one is
+    // configured or issued by the manager
+    token: RwLock<String>,
+    // where an issued token is kept, to be presented again after a restart
+    token_file: RwLock<Option<PathBuf>>,
+}
+
+impl ManagerClient {
+    /// A client for the manager at `cfg.api_base`, presenting the `(cert, key)`
+    /// PEM files of `identity` to a manager that verifies its workers.
+    pub fn new(
+        cfg: &WorkerManagerConfig,
+        identity: Option<(&str, &str)>,
+    ) -> Result<Self, ClientError> {
+        let defaults = WorkerManagerConfig::default();
+        let api_base = cfg
+            .api_base
+            .clone()
+            .or(defaults.api_base)
+            .unwrap_or_default();
+        let ca_cert = cfg.ca_cert.as_deref().filter(|c| !c.is_empty());
+        let client = create_http_client_with_identity(ca_cert, identity)
+            .map_err(|e| ClientError::Http(e.to_string()))?;
+        let client = ManagerClient {
+            api_base: api_base.trim_end_matches('/').to_string(),
+            client,
+            token: RwLock::new(cfg.token.clone().unwrap_or_default()),
+            token_file: RwLock::new(None),
+        };
+        if let Some(path) = cfg.token_file.as_deref().filter(|f| !f.is_empty()) {
+            client.keep_token_in(path);
+        }
+        Ok(client)
+    }
+
+    pub fn api_base(&self) -> &str {
+        &self.api_base
+    }
+
+    pub fn token(&self) -> String {
+        self.token
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set_token(&self, token: String) {
+        *self.token.write().unwrap_or_else(PoisonError::into_inner) = token;
+    }
+
+    pub fn token_file(&self) -> Option<PathBuf> {
+        self.token_file
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Save the tokens the manager issues to `path`, and start from the one
+    /// saved there when no token is configured.
+    pub fn keep_token_in(&self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        if self.token().is_empty() {
+            match fs::read_to_string(&path) {
+                Ok(token) => self.set_token(token.trim().to_string()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to read token file {}: {}", path.display(), e),
+            }
+        }
+        *self
+            .token_file
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(path);
+    }
+
+    // a token the manager gave out is lost with the worker unless saved
+    fn save_token(&self) {
+        let Some(path) = self.token_file() else {
+            return;
+        };
+        if let Err(e) = write_secret(&path, &self.token()) {
+            warn!("Failed to save token to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Register `worker` with the token of this client, and keep the token the
+    /// manager answers with, which it issues when the worker has none.
+    pub fn register_worker(&self, worker: &WorkerStatus) -> Result<WorkerStatus, ClientError> {
+        let url = format!("{}/workers", self.api_base);
+        debug!("register on manager url: {}", url);
+        let worker = WorkerStatus {
+            token: self.token(),
+            ..worker.clone()
+        };
+        let resp = self.post(&url, &worker)?;
+        let registered: WorkerStatus =
+            serde_json::from_value(resp).map_err(|e| ClientError::Decode(e.to_string()))?;
+        if registered.token != worker.token {
+            self.set_token(registered.token.clone());
+            self.save_token();
+        }
+        Ok(registered)
+    }
+
+    /// Replace the token of `worker_id` on the manager with `token`, or with
+    /// one the manager issues if it is empty.
+    pub fn rotate_token(&self, worker_id: &str, token: &str) -> Result<(), ClientError> {
+        let url = format!("{}/workers/{}/token", self.api_base, worker_id);
+        let resp = self.post(&url, &serde_json::json!({ "token": token }))?;
+        let worker: WorkerStatus =
+            serde_json::from_value(resp).map_err(|e| ClientError::Decode(e.to_string()))?;
+        self.set_token(worker.token);
+        self.save_token();
+        Ok(())
+    }
+
+    pub fn heartbeat(&self, worker_id: &str) -> Result<WorkerStatus, ClientError> {
+        let url = format!("{}/workers/{}/heartbeat", self.api_base, worker_id);
+        let resp = self.post(&url, &Value::Null)?;
+        serde_json::from_value(resp).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    pub fn list_jobs(&self, worker_id: &str) -> Result<Vec<MirrorStatus>, ClientError> {
+        let url = format!("{}/workers/{}/jobs", self.api_base, worker_id);
+        get_json(&url, Some(&self.client)).map_err(|e| ClientError::Http(e.to_string()))
+    }
+
+    pub fn update_job_status(
+        &self,
+        worker_id: &str,
+        status: &MirrorStatus,
+    ) -> Result<MirrorStatus, ClientError> {
+        let url = format!(
+            "{}/workers/{}/jobs/{}",
+            self.api_base, worker_id, status.name
+        );
+        let resp = self.post(&url, status)?;
+        serde_json::from_value(resp).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    pub fn update_schedules(
+        &self,
+        worker_id: &str,
+        schedules: &MirrorSchedules,
+    ) -> Result<(), ClientError> {
+        let url = format!("{}/workers/{}/schedules", self.api_base, worker_id);
+        self.post(&url, schedules).map(|_| ())
+    }
+
+    fn post<T: Serialize>(&self, url: &str, obj: &T) -> Result<Value, ClientError> {
+        let resp = self
+            .client
+            .post(url)
+            .bearer_auth(self.token())
+            .json(obj)
+            .send()
+            .map_err(|e| ClientError::Http(e.to_string()))?;
+        let status = resp.status();
+        let body = resp.text().map_err(|e| ClientError::Http(e.to_string()))?;
+        if !status.is_success() {
+            return Err(ClientError::Status(status.as_u16(), body));
+        }
+        serde_json::from_str(&body).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+}
+
+// readable by the owner only, and replaced whole so a crash leaves the old one
+fn write_secret(path: &Path, secret: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(secret.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Register `worker` on the manager, retrying a few times before giving up.
+pub async fn register_with_retry(
+    client: Arc<ManagerClient>,
+    worker: WorkerStatus,
+) -> Result<WorkerStatus, ClientError> {
+    let worker = Arc::new(worker);
+    let mut retry = REGISTER_RETRIES;
+    loop {
+        let c = Arc::clone(&client);
+        let w = Arc::clone(&worker);
+        match tokio::task::spawn_blocking(move || c.register_worker(&w)).await? {
+            Ok(registered) => {
+                info!(
+                    "Worker <{}> registered on {}",
+                    registered.id,
+                    client.api_base()
+                );
+                return Ok(registered);
+            }
+            Err(e) => {
+                error!("Failed to register worker: {}", e);
+                retry -= 1;
+                if retry == 0 {
+                    return Err(e);
+                }
+                tokio::time::sleep(REGISTER_RETRY_DELAY).await;
+                warn!("Retrying... ({})", retry);
+            }
+        }
+    }
+}
+
+/// Report liveness of `worker_id` to the manager every `interval`, forever.
+pub async fn heartbeat_loop(client: Arc<ManagerClient>, worker_id: String, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick completes immediately, right after registration
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match send_heartbeat(Arc::clone(&client), worker_id.clone()).await {
+            Ok(w) => debug!("Heartbeat of worker <{}> at {}", w.id, w.last_online),
+            Err(e) => warn!("Failed to send heartbeat: {}", e),
+        }
+    }
+}
+
+async fn send_heartbeat(
+    client: Arc<ManagerClient>,
+    worker_id: String,
+) -> Result<WorkerStatus, ClientError> {
+    tokio::task::spawn_blocking(move || client.heartbeat(&worker_id)).await?
+}
@@ -0,0 +1,27 @@
+use std::error::Error;
+use std::path::Path;
+
+use hustsync_config_parser::{WorkerConfig, WorkerServerConfig};
+
+pub fn load_config(cfg_file: impl AsRef<Path>) -> Result<WorkerConfig, Box<dyn Error>> {
+    hustsync_config_parser::parse_config::<WorkerConfig>(cfg_file)
+}
+
+/// The URL the manager uses to reach this worker, e.g. `http://localhost:6000/`.
+pub fn worker_url(server: Option<&WorkerServerConfig>) -> String {
+    let defaults = WorkerServerConfig::default();
+    let hostname = server
+        .and_then(|s| s.hostname.clone())
+        .or(defaults.hostname)
+        .unwrap_or_default();
+    let port = server
+        .and_then(|s| s.listen_port)
+        .or(defaults.listen_port)
+        .unwrap_or_default();
+    let has_tls = server.is_some_and(|s| {
+        s.ssl_cert.as_deref().is_some_and(|c| !c.is_empty())
+            && s.ssl_key.as_deref().is_some_and(|k| !k.is_empty())
+    });
+    let proto = if has_tls { "https" } else { "http" };
+    format!("{}://{}:{}/", proto, hostname, port)
+}
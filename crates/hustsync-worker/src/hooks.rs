@@ -0,0 +1,147 @@
+use std::process::ExitStatus;
+
+use hustsync_config_parser::{MirrorConfig, WorkerGlobalConfig};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::provider::{BaseProvider, ProviderCmd, ProviderError};
+use crate::runner::{RunError, run_command};
+
+/// Commands run after each sync attempt, as configured by `exec_on_success`
+/// and `exec_on_failure`.
+#[derive(Debug, Default)]
+pub(crate) struct ExecHooks {
+    on_success: Vec<Vec<String>>,
+    on_failure: Vec<Vec<String>>,
+}
+
+impl ExecHooks {
+    /// The hooks of a mirror replace the global ones, and its `*_extra` hooks are
+    /// appended to either.
+    pub(crate) fn new(
+        global: &WorkerGlobalConfig,
+        mirror: &MirrorConfig,
+    ) -> Result<Self, ProviderError> {
+        let name = mirror.name.clone().unwrap_or_default();
+        let exec = mirror.exec_on_status.as_ref();
+        let global_exec = global.exec_on_status.as_ref();
+        let extra = mirror.exec_on_status_extra.as_ref();
+
+        let on_success = exec
+            .and_then(|e| e.exec_on_success.clone())
+            .or_else(|| global_exec.and_then(|e| e.exec_on_success.clone()))
+            .into_iter()
+            .chain(extra.and_then(|e| e.exec_on_success_extra.clone()))
+            .flatten();
+        let on_failure = exec
+            .and_then(|e| e.exec_on_failure.clone())
+            .or_else(|| global_exec.and_then(|e| e.exec_on_failure.clone()))
+            .into_iter()
+            .chain(extra.and_then(|e| e.exec_on_failure_extra.clone()))
+            .flatten();
+
+        Ok(ExecHooks {
+            on_success: split_commands(&name, on_success)?,
+            on_failure: split_commands(&name, on_failure)?,
+        })
+    }
+
+    /// Run the hooks matching the outcome of a sync, one after another.
+    ///
+    /// A failing hook is logged and does not change the outcome of the sync.
+    pub(crate) async fn run(&self, base: &BaseProvider, success: bool, cancel: &CancellationToken) {
+        let (hooks, exit_status) = if success {
+            (&self.on_success, "success")
+        } else {
+            (&self.on_failure, "failure")
+        };
+        for hook in hooks {
+            let Some((program, args)) = hook.split_first() else {
+                continue;
+            };
+            let mut env = base.job_env();
+            env.insert("TUNASYNC_JOB_EXIT_STATUS".into(), exit_status.into());
+            let cmd = ProviderCmd {
+                program: program.clone(),
+                args: args.to_vec(),
+                env,
+            };
+            let result = run_command(&cmd, &base.working_dir, &base.log_file, None, cancel).await;
+            log_hook_result(&base.name, hook, result);
+        }
+    }
+}
+
+fn log_hook_result(name: &str, hook: &[String], result: Result<ExitStatus, RunError>) {
+    match result {
+        Ok(status) if status.success() => {
+            info!("hook {:?} of {} exited with {}", hook, name, status)
+        }
+        Ok(status) => warn!("hook {:?} of {} exited with {}", hook, name, status),
+        Err(e) => warn!("hook {:?} of {} failed: {}", hook, name, e),
+    }
+}
+
+fn split_commands(
+    name: &str,
+    commands: impl Iterator<Item = String>,
+) -> Result<Vec<Vec<String>>, ProviderError> {
+    commands
+        .map(|raw| {
+            shlex::split(&raw).filter(|c| !c.is_empty()).ok_or_else(|| {
+                ProviderError::InvalidConfig(name.to_string(), format!("bad hook '{}'", raw))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hustsync_config_parser::{ExecOnStatus, ExecOnStatusExtra};
+
+    fn exec_on(success: &[&str], failure: &[&str]) -> Option<ExecOnStatus> {
+        let to_vec = |cmds: &[&str]| {
+            (!cmds.is_empty()).then(|| cmds.iter().map(|c| c.to_string()).collect())
+        };
+        Some(ExecOnStatus {
+            exec_on_success: to_vec(success),
+            exec_on_failure: to_vec(failure),
+        })
+    }
+
+    #[test]
+    fn mirror_hooks_should_replace_global_ones() {
+        let global = WorkerGlobalConfig {
+            exec_on_status: exec_on(&["purge-cdn"], &["notify admin"]),
+            ..WorkerGlobalConfig::default()
+        };
+        let mirror = MirrorConfig {
+            exec_on_status: exec_on(&["reindex --all"], &[]),
+            exec_on_status_extra: Some(ExecOnStatusExtra {
+                exec_on_success_extra: Some(vec!["touch done".into()]),
+                exec_on_failure_extra: Some(vec!["touch failed".into()]),
+            }),
+            ..MirrorConfig::default()
+        };
+        let hooks = ExecHooks::new(&global, &mirror).unwrap();
+
+        assert_eq!(
+            hooks.on_success,
+            [vec!["reindex", "--all"], vec!["touch", "done"]]
+        );
+        assert_eq!(
+            hooks.on_failure,
+            [vec!["notify", "admin"], vec!["touch", "failed"]]
+        );
+    }
+
+    #[test]
+    fn bad_hook_should_be_rejected() {
+        let mirror = MirrorConfig {
+            exec_on_status: exec_on(&["echo 'unterminated"], &[]),
+            ..MirrorConfig::default()
+        };
+        assert!(ExecHooks::new(&WorkerGlobalConfig::default(), &mirror).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hustsync_internal::status::SyncStatus;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::cgroup::Cgroup;
+use crate::hooks::ExecHooks;
+use crate::provider::{BaseProvider, MirrorProvider};
+use crate::runner::{RunError, run_command};
+
+/// Control signals sent to a running job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JobCtrl {
+    Start,
+    /// Start without waiting for the other syncs, beyond the concurrency limit.
+    ForceStart,
+    Stop,
+    Disable,
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JobState {
+    /// Scheduled periodically.
+    Ready,
+    /// Stopped by the operator, waits for an explicit start.
+    Paused,
+    /// Taken out of scheduling altogether.
+    Disabled,
+}
+
+/// Messages a job sends back to the worker, to be relayed to the manager.
+#[derive(Debug, Clone)]
+pub(crate) enum JobMsg {
+    Status {
+        name: String,
+        upstream: String,
+        is_master: bool,
+        status: SyncStatus,
+        msg: String,
+        size: Option<String>,
+    },
+    Schedule {
+        name: String,
+        next: DateTime<Utc>,
+    },
+}
+
+/// What every job of a worker shares.
+#[derive(Clone)]
+pub(crate) struct JobContext {
+    pub msg_tx: mpsc::UnboundedSender<JobMsg>,
+    pub semaphore: Arc<Semaphore>,
+    pub shutdown: CancellationToken,
+}
+
+// first delay between two attempts of a sync, doubled after each failure
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+enum SyncError {
+    Cancelled,
+    Failed(String),
+    TimedOut(Duration),
+}
+
+impl From<RunError> for SyncError {
+    fn from(e: RunError) -> Self {
+        match e {
+            RunError::Cancelled => SyncError::Cancelled,
+            RunError::Io(e) => SyncError::Failed(e.to_string()),
+        }
+    }
+}
+
+pub(crate) struct MirrorJob {
+    provider: Box<dyn MirrorProvider>,
+    hooks: ExecHooks,
+    cgroup: Option<Cgroup>,
+    state: Mutex<JobState>,
+}
+
+impl MirrorJob {
+    pub(crate) fn new(
+        provider: Box<dyn MirrorProvider>,
+        hooks: ExecHooks,
+        cgroup: Option<Cgroup>,
+    ) -> Self {
+        MirrorJob {
+            provider,
+            hooks,
+            cgroup,
+            state: Mutex::new(JobState::Ready),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.provider.base().name
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.provider.base().interval
+    }
+
+    pub(crate) fn state(&self) -> JobState {
+        *self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn set_state(&self, state: JobState) {
+        *self.state.lock().unwrap_or_else(PoisonError::into_inner) = state;
+    }
+
+    fn report(&self, ctx: &JobContext, status: SyncStatus, msg: String, size: Option<String>) {
+        let base = self.provider.base();
+        let _ = ctx.msg_tx.send(JobMsg::Status {
+            name: base.name.clone(),
+            upstream: base.upstream.clone(),
+            is_master: base.is_master,
+            status,
+            msg,
+            size,
+        });
+    }
+
+    /// Tell the manager the job is gone, e.g. after its mirror was removed from the config.
+    pub(crate) fn report_disabled(&self, ctx: &JobContext) {
+        self.set_state(JobState::Disabled);
+        self.report(
+            ctx,
+            SyncStatus::Disabled,
+            "removed from config".into(),
+            None,
+        );
+    }
+
+    /// Drive the job until the worker shuts down: sync on schedule and obey control signals.
+    pub(crate) async fn run(
+        self: Arc<Self>,
+        mut ctrl_rx: mpsc::UnboundedReceiver<JobCtrl>,
+        ctx: JobContext,
+        first_run: Instant,
+    ) {
+        let mut next_run = first_run;
+        let mut force = false;
+        loop {
+            let ready = self.state() == JobState::Ready;
+            tokio::select! {
+                _ = ctx.shutdown.cancelled() => return,
+                ctrl = ctrl_rx.recv() => {
+                    let Some(ctrl) = ctrl else { return };
+                    if self.handle_idle_ctrl(ctrl, &ctx) {
+                        next_run = Instant::now();
+                        force = ctrl == JobCtrl::ForceStart;
+                    }
+                }
+                _ = tokio::time::sleep_until(next_run), if ready => {
+                    let force = std::mem::take(&mut force);
+                    let restart = self.sync(&mut ctrl_rx, &ctx, force).await;
+                    next_run = self.schedule_next(restart, &ctx);
+                }
+            }
+        }
+    }
+
+    /// Apply a control signal received while not syncing, returning whether to sync right away.
+    fn handle_idle_ctrl(&self, ctrl: JobCtrl, ctx: &JobContext) -> bool {
+        match ctrl {
+            JobCtrl::Start | JobCtrl::ForceStart | JobCtrl::Restart => {
+                self.set_state(JobState::Ready);
+                true
+            }
+            JobCtrl::Stop => {
+                self.set_state(JobState::Paused);
+                self.report(ctx, SyncStatus::Paused, String::new(), None);
+                false
+            }
+            JobCtrl::Disable => {
+                self.set_state(JobState::Disabled);
+                self.report(ctx, SyncStatus::Disabled, String::new(), None);
+                false
+            }
+        }
+    }
+
+    fn schedule_next(&self, restart: bool, ctx: &JobContext) -> Instant {
+        if restart {
+            return Instant::now();
+        }
+        let interval = self.provider.base().interval;
+        if self.state() == JobState::Ready {
+            let next = Utc::now() + interval;
+            debug!("next sync of {} scheduled at {}", self.name(), next);
+            let _ = ctx.msg_tx.send(JobMsg::Schedule {
+                name: self.name().to_string(),
+                next,
+            });
+        }
+        Instant::now() + interval
+    }
+
+    /// Run one sync while listening for control signals, returning whether a restart was asked.
+    async fn sync(
+        &self,
+        ctrl_rx: &mut mpsc::UnboundedReceiver<JobCtrl>,
+        ctx: &JobContext,
+        force: bool,
+    ) -> bool {
+        let cancel = CancellationToken::new();
+        let sync = self.run_sync(&cancel, ctx, force);
+        tokio::pin!(sync);
+
+        let mut restart = false;
+        loop {
+            tokio::select! {
+                _ = &mut sync => return restart,
+                _ = ctx.shutdown.cancelled(), if !cancel.is_cancelled() => cancel.cancel(),
+                Some(ctrl) = ctrl_rx.recv(), if !cancel.is_cancelled() => {
+                    restart = self.handle_busy_ctrl(ctrl, &cancel);
+                }
+            }
+        }
+    }
+
+    /// Apply a control signal received while syncing, returning whether to restart.
+    fn handle_busy_ctrl(&self, ctrl: JobCtrl, cancel: &CancellationToken) -> bool {
+        match ctrl {
+            JobCtrl::Start | JobCtrl::ForceStart => return false,
+            JobCtrl::Stop => self.set_state(JobState::Paused),
+            JobCtrl::Disable => self.set_state(JobState::Disabled),
+            JobCtrl::Restart => {}
+        }
+        info!("terminating the running sync of {}", self.name());
+        cancel.cancel();
+        ctrl == JobCtrl::Restart
+    }
+
+    async fn run_sync(&self, cancel: &CancellationToken, ctx: &JobContext, force: bool) {
+        // a forced sync runs on top of the concurrent ones, as in tunasync
+        let _permit = if force {
+            None
+        } else {
+            tokio::select! {
+                permit = ctx.semaphore.acquire() => match permit {
+                    Ok(permit) => Some(permit),
+                    Err(_) => return,
+                },
+                _ = cancel.cancelled() => return self.report_cancelled(ctx),
+            }
+        };
+
+        info!("start syncing: {}", self.name());
+        self.report(ctx, SyncStatus::PreSyncing, String::new(), None);
+        self.sync_with_retry(cancel, ctx).await;
+    }
+
+    /// Attempt the sync until it succeeds, is stopped or runs out of retries.
+    async fn sync_with_retry(&self, cancel: &CancellationToken, ctx: &JobContext) {
+        let retry = self.provider.base().retry;
+        let mut backoff = RETRY_BACKOFF;
+        for attempt in 0..retry {
+            if attempt > 0 {
+                info!("retry syncing: {}, retry: {}", self.name(), attempt);
+            }
+            self.report(ctx, SyncStatus::Syncing, String::new(), None);
+            let result = self.run_attempt(cancel, attempt > 0).await;
+            self.run_hooks(&result, cancel).await;
+            // as in tunasync, a sync that timed out is not retried
+            let retriable = matches!(result, Err(SyncError::Failed(_)));
+            self.report_result(ctx, result);
+            if !retriable || attempt + 1 == retry {
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = cancel.cancelled() => return self.report_cancelled(ctx),
+            }
+            backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+        }
+    }
+
+    async fn run_hooks(
+        &self,
+        result: &Result<Option<String>, SyncError>,
+        cancel: &CancellationToken,
+    ) {
+        let base = self.provider.base();
+        match result {
+            Ok(_) => self.hooks.run(base, true, cancel).await,
+            // stopped on purpose, not a failure of the sync
+            Err(SyncError::Cancelled) => {}
+            Err(_) => self.hooks.run(base, false, cancel).await,
+        }
+    }
+
+    /// Run the provider once, killing it if it outlives the timeout of the job.
+    async fn run_attempt(
+        &self,
+        cancel: &CancellationToken,
+        append_log: bool,
+    ) -> Result<Option<String>, SyncError> {
+        let Some(timeout) = self.provider.base().timeout else {
+            return self.run_provider(cancel, append_log).await;
+        };
+        let attempt = cancel.child_token();
+        let run = self.run_provider(&attempt, append_log);
+        tokio::pin!(run);
+        match tokio::time::timeout(timeout, &mut run).await {
+            Ok(result) => result,
+            Err(_) => {
+                attempt.cancel();
+                // wait for the commands to be reaped
+                let _ = run.await;
+                Err(SyncError::TimedOut(timeout))
+            }
+        }
+    }
+
+    fn report_result(&self, ctx: &JobContext, result: Result<Option<String>, SyncError>) {
+        match result {
+            Ok(size) => {
+                info!("succeeded syncing {}", self.name());
+                self.report(ctx, SyncStatus::Success, String::new(), size);
+            }
+            Err(SyncError::Failed(msg)) => {
+                warn!("failed syncing {}: {}", self.name(), msg);
+                self.report(ctx, SyncStatus::Failed, msg, None);
+            }
+            Err(SyncError::TimedOut(timeout)) => {
+                let msg = format!("{} timeout after {:?}", self.name(), timeout);
+                warn!("failed syncing {}: {}", self.name(), msg);
+                self.report(ctx, SyncStatus::Failed, msg, None);
+            }
+            Err(SyncError::Cancelled) => self.report_cancelled(ctx),
+        }
+    }
+
+    fn report_cancelled(&self, ctx: &JobContext) {
+        match self.state() {
+            JobState::Paused => {
+                self.report(ctx, SyncStatus::Paused, "killed by manager".into(), None)
+            }
+            JobState::Disabled => {
+                self.report(ctx, SyncStatus::Disabled, "killed by manager".into(), None)
+            }
+            // restarting or shutting down, the next status will tell
+            JobState::Ready => {}
+        }
+    }
+
+    async fn run_provider(
+        &self,
+        cancel: &CancellationToken,
+        append_log: bool,
+    ) -> Result<Option<String>, SyncError> {
+        let base = self.provider.base();
+        prepare_dirs(base, append_log).map_err(|e| SyncError::Failed(e.to_string()))?;
+        let cgroup = self.cgroup.as_ref();
+        if let Some(cgroup) = cgroup {
+            cgroup
+                .create()
+                .map_err(|e| SyncError::Failed(format!("failed to set up cgroup: {}", e)))?;
+        }
+
+        for cmd in self.provider.commands() {
+            let status =
+                run_command(&cmd, &base.working_dir, &base.log_file, cgroup, cancel).await?;
+            self.provider
+                .check_exit_status(&status)
+                .map_err(SyncError::Failed)?;
+        }
+        self.provider.after_sync().map_err(SyncError::Failed)
+    }
+}
+
+// create the working and log directories, and start the log afresh unless retrying
+fn prepare_dirs(base: &BaseProvider, append_log: bool) -> std::io::Result<()> {
+    fs::create_dir_all(&base.working_dir)?;
+    fs::create_dir_all(&base.log_dir)?;
+    if append_log {
+        Ok(())
+    } else {
+        fs::write(&base.log_file, b"")
+    }
+}
@@ -0,0 +1,16 @@
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
+
+mod cgroup;
+mod client;
+mod config;
+mod hooks;
+mod job;
+mod provider;
+mod runner;
+mod server;
+mod worker;
+
+pub use client::{ClientError, ManagerClient, heartbeat_loop, register_with_retry};
+pub use config::{load_config, worker_url};
+pub use provider::ProviderError;
+pub use worker::{Worker, WorkerError, get_hustsync_worker};
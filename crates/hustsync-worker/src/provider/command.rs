@@ -0,0 +1,146 @@
+use hustsync_config_parser::MirrorConfig;
+use hustsync_internal::util::{extract_size_from_log, find_all_submatch_in_file};
+use regex::Regex;
+
+use super::{BaseProvider, MirrorProvider, ProviderCmd, ProviderError};
+
+/// Runs the `command` of a mirror as-is, e.g. a custom sync script.
+pub(crate) struct CmdProvider {
+    base: BaseProvider,
+    command: Vec<String>,
+    // a sync fails if its log matches this
+    fail_on_match: Option<Regex>,
+    // the first capture group is the mirror size
+    size_pattern: Option<Regex>,
+}
+
+impl CmdProvider {
+    pub(crate) fn new(base: BaseProvider, mirror: &MirrorConfig) -> Result<Self, ProviderError> {
+        let raw = mirror.command.as_deref().unwrap_or_default();
+        let command = shlex::split(raw).filter(|c| !c.is_empty()).ok_or_else(|| {
+            ProviderError::InvalidConfig(base.name.clone(), format!("bad command '{}'", raw))
+        })?;
+        let fail_on_match = compile_pattern(&base, mirror.fail_on_match.as_deref())?;
+        let size_pattern = compile_pattern(&base, mirror.size_pattern.as_deref())?;
+        Ok(CmdProvider {
+            base,
+            command,
+            fail_on_match,
+            size_pattern,
+        })
+    }
+}
+
+fn compile_pattern(
+    base: &BaseProvider,
+    pattern: Option<&str>,
+) -> Result<Option<Regex>, ProviderError> {
+    match pattern {
+        Some(p) if !p.is_empty() => Regex::new(p).map(Some).map_err(|e| {
+            ProviderError::InvalidConfig(base.name.clone(), format!("bad pattern '{}': {}", p, e))
+        }),
+        _ => Ok(None),
+    }
+}
+
+impl MirrorProvider for CmdProvider {
+    fn base(&self) -> &BaseProvider {
+        &self.base
+    }
+
+    fn provider_type(&self) -> &'static str {
+        "command"
+    }
+
+    fn commands(&self) -> Vec<ProviderCmd> {
+        let (program, args) = match self.command.split_first() {
+            Some((program, args)) => (program.clone(), args.to_vec()),
+            None => (String::new(), Vec::new()),
+        };
+        vec![ProviderCmd {
+            program,
+            args,
+            // the mirror env may override the job context
+            env: self
+                .base
+                .job_env()
+                .into_iter()
+                .chain(self.base.env.clone())
+                .collect(),
+        }]
+    }
+
+    fn after_sync(&self) -> Result<Option<String>, String> {
+        let log_file = self.base.log_file.to_string_lossy();
+        if let Some(re) = &self.fail_on_match {
+            let matches = find_all_submatch_in_file(&log_file, re).map_err(|e| e.to_string())?;
+            if !matches.is_empty() {
+                return Err(format!(
+                    "Fail-on-match regexp found {} matches",
+                    matches.len()
+                ));
+            }
+        }
+        let size = self
+            .size_pattern
+            .as_ref()
+            .map(|re| extract_size_from_log(&log_file, re))
+            .filter(|size| !size.is_empty());
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hustsync_config_parser::WorkerGlobalConfig;
+    use std::collections::HashMap;
+
+    fn make_provider(mirror: MirrorConfig) -> Result<CmdProvider, ProviderError> {
+        let base = BaseProvider::new(&WorkerGlobalConfig::default(), &mirror)?;
+        CmdProvider::new(base, &mirror)
+    }
+
+    fn script_mirror() -> MirrorConfig {
+        MirrorConfig {
+            provider: Some("command".into()),
+            command: Some("./sync.sh --verbose 'a b'".into()),
+            env: Some(HashMap::from([
+                (
+                    "TUNASYNC_UPSTREAM_URL".to_string(),
+                    "https://mirror/".to_string(),
+                ),
+                ("REPO".to_string(), "elvish".to_string()),
+            ])),
+            ..MirrorConfig::default()
+        }
+    }
+
+    #[test]
+    fn command_should_export_job_env() {
+        let provider = make_provider(script_mirror()).unwrap();
+        let cmds = provider.commands();
+        assert_eq!(cmds[0].program, "./sync.sh");
+        assert_eq!(cmds[0].args, ["--verbose", "a b"]);
+
+        let env = &cmds[0].env;
+        assert_eq!(env["TUNASYNC_MIRROR_NAME"], "elvish");
+        assert_eq!(env["TUNASYNC_WORKING_DIR"], "/tmp/tunasync/elvish");
+        assert_eq!(
+            env["TUNASYNC_LOG_FILE"],
+            "/tmp/tunasync/log/tunasync/elvish/latest.log"
+        );
+        assert_eq!(env["REPO"], "elvish");
+        // the mirror env wins
+        assert_eq!(env["TUNASYNC_UPSTREAM_URL"], "https://mirror/");
+    }
+
+    #[test]
+    fn command_should_reject_bad_patterns() {
+        let mirror = MirrorConfig {
+            size_pattern: Some("size: ([0-9".into()),
+            ..script_mirror()
+        };
+        assert!(make_provider(mirror).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use hustsync_config_parser::{MirrorConfig, RetryStrategy, WorkerGlobalConfig};
+use thiserror::Error;
+
+mod command;
+mod rsync;
+mod two_stage_rsync;
+
+use command::CmdProvider;
+use rsync::RsyncProvider;
+use two_stage_rsync::TwoStageRsyncProvider;
+
+const LOG_FILE_NAME: &str = "latest.log";
+const DEFAULT_MAX_RETRY: u32 = 2;
+const NAME_PLACEHOLDER: &str = "{{.Name}}";
+
+#[derive(Error, Debug)]
+pub enum ProviderError {
+    #[error("mirror name should not be empty")]
+    MissingName,
+    #[error("unsupported provider '{1}' of mirror {0}")]
+    Unsupported(String, String),
+    #[error("invalid config of mirror {0}: {1}")]
+    InvalidConfig(String, String),
+}
+
+/// A command line run by a provider, together with its extra environment.
+#[derive(Debug, Clone)]
+pub(crate) struct ProviderCmd {
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+}
+
+/// Settings shared by every provider, resolved from the `[global]` and `[[mirrors]]` sections.
+#[derive(Debug, Clone)]
+pub(crate) struct BaseProvider {
+    pub name: String,
+    pub upstream: String,
+    pub working_dir: PathBuf,
+    pub log_dir: PathBuf,
+    pub log_file: PathBuf,
+    pub is_master: bool,
+    pub env: HashMap<String, String>,
+    pub interval: Duration,
+    /// Attempts of a sync before it is reported failed for good.
+    pub retry: u32,
+    /// A sync running longer than this is killed.
+    pub timeout: Option<Duration>,
+    // non-zero exit codes to be treated as success anyway
+    pub success_exit_codes: Vec<i32>,
+}
+
+impl BaseProvider {
+    fn new(global: &WorkerGlobalConfig, mirror: &MirrorConfig) -> Result<Self, ProviderError> {
+        let defaults = WorkerGlobalConfig::default();
+        let name = mirror
+            .name
+            .clone()
+            .filter(|n| !n.is_empty())
+            .ok_or(ProviderError::MissingName)?;
+
+        let working_dir = match &mirror.mirror_dir {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                let root = global
+                    .mirror_dir
+                    .clone()
+                    .or(defaults.mirror_dir)
+                    .unwrap_or_default();
+                PathBuf::from(root).join(&name)
+            }
+        };
+
+        let log_dir_tpl = mirror
+            .log_dir
+            .clone()
+            .or_else(|| global.log_dir.clone())
+            .or(defaults.log_dir)
+            .unwrap_or_default();
+        let log_dir = PathBuf::from(log_dir_tpl.replace(NAME_PLACEHOLDER, &name));
+
+        let interval = retry_strategy(global, mirror, |r| r.interval)
+            .or_else(|| defaults.retry.as_ref().and_then(|r| r.interval))
+            .unwrap_or_default();
+        let retry = retry_strategy(global, mirror, |r| r.retry)
+            .filter(|&r| r > 0)
+            .unwrap_or(DEFAULT_MAX_RETRY);
+        let timeout = retry_strategy(global, mirror, |r| r.timeout)
+            .filter(|&t| t > 0)
+            .map(|t| Duration::from_secs(u64::from(t)));
+
+        Ok(BaseProvider {
+            upstream: mirror.upstream.clone().unwrap_or_default(),
+            working_dir,
+            log_file: log_dir.join(LOG_FILE_NAME),
+            log_dir,
+            is_master: mirror.role.as_deref() != Some("slave"),
+            env: mirror.env.clone().unwrap_or_default(),
+            interval: Duration::from_secs(u64::from(interval) * 60),
+            retry,
+            timeout,
+            success_exit_codes: global
+                .dangerous_global_success_exit_codes
+                .clone()
+                .unwrap_or_default(),
+            name,
+        })
+    }
+
+    /// Context of the job exported to the commands it runs.
+    pub(crate) fn job_env(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("TUNASYNC_MIRROR_NAME".to_string(), self.name.clone()),
+            (
+                "TUNASYNC_WORKING_DIR".to_string(),
+                self.working_dir.to_string_lossy().into_owned(),
+            ),
+            ("TUNASYNC_UPSTREAM_URL".to_string(), self.upstream.clone()),
+            (
+                "TUNASYNC_LOG_DIR".to_string(),
+                self.log_dir.to_string_lossy().into_owned(),
+            ),
+            (
+                "TUNASYNC_LOG_FILE".to_string(),
+                self.log_file.to_string_lossy().into_owned(),
+            ),
+        ])
+    }
+
+    pub(crate) fn is_success(&self, status: &ExitStatus) -> bool {
+        status.success()
+            || status
+                .code()
+                .is_some_and(|code| self.success_exit_codes.contains(&code))
+    }
+}
+
+// a setting of the mirror, falling back to the global one
+fn retry_strategy(
+    global: &WorkerGlobalConfig,
+    mirror: &MirrorConfig,
+    field: impl Fn(&RetryStrategy) -> Option<u32>,
+) -> Option<u32> {
+    mirror
+        .retry
+        .as_ref()
+        .and_then(&field)
+        .or_else(|| global.retry.as_ref().and_then(&field))
+}
+
+pub(crate) trait MirrorProvider: Send + Sync {
+    fn base(&self) -> &BaseProvider;
+
+    fn provider_type(&self) -> &'static str;
+
+    /// Command lines run, in order, by one sync attempt.
+    fn commands(&self) -> Vec<ProviderCmd>;
+
+    /// Turn the exit status of a command into an error message if it failed.
+    fn check_exit_status(&self, status: &ExitStatus) -> Result<(), String> {
+        if self.base().is_success(status) {
+            Ok(())
+        } else {
+            Err(format!("{} exited with {}", self.provider_type(), status))
+        }
+    }
+
+    /// Inspect the job log once every command succeeded, returning the mirror size if known.
+    fn after_sync(&self) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+}
+
+pub(crate) fn new_provider(
+    global: &WorkerGlobalConfig,
+    mirror: &MirrorConfig,
+) -> Result<Box<dyn MirrorProvider>, ProviderError> {
+    let base = BaseProvider::new(global, mirror)?;
+    let provider = mirror.provider.clone().unwrap_or_default();
+    match provider.as_str() {
+        "rsync" => Ok(Box::new(RsyncProvider::new(base, global, mirror)?)),
+        "two-stage-rsync" => Ok(Box::new(TwoStageRsyncProvider::new(base, global, mirror)?)),
+        "command" => Ok(Box::new(CmdProvider::new(base, mirror)?)),
+        _ => Err(ProviderError::Unsupported(base.name, provider)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_provider_should_resolve_defaults() {
+        let global = WorkerGlobalConfig::default();
+        let mirror = MirrorConfig::default();
+        let base = BaseProvider::new(&global, &mirror).unwrap();
+
+        assert_eq!(base.name, "elvish");
+        assert_eq!(base.upstream, "rsync://rsync.elv.sh/elvish/");
+        assert_eq!(base.working_dir, PathBuf::from("/tmp/tunasync/elvish"));
+        assert_eq!(
+            base.log_dir,
+            PathBuf::from("/tmp/tunasync/log/tunasync/elvish")
+        );
+        assert_eq!(
+            base.log_file,
+            PathBuf::from("/tmp/tunasync/log/tunasync/elvish/latest.log")
+        );
+        assert!(base.is_master);
+        assert_eq!(base.interval, Duration::from_secs(120 * 60));
+        assert_eq!(base.retry, 2);
+        assert_eq!(base.timeout, None);
+    }
+
+    #[test]
+    fn base_provider_should_prefer_mirror_settings() {
+        let global = WorkerGlobalConfig {
+            retry: Some(RetryStrategy {
+                retry: Some(5),
+                timeout: Some(3600),
+                interval: Some(60),
+            }),
+            ..WorkerGlobalConfig::default()
+        };
+        let mirror = MirrorConfig {
+            retry: Some(RetryStrategy {
+                retry: None,
+                timeout: Some(600),
+                interval: Some(5),
+            }),
+            mirror_dir: Some("/srv/mirrors/elv".into()),
+            log_dir: Some("/var/log/{{.Name}}-sync".into()),
+            role: Some("slave".into()),
+            ..MirrorConfig::default()
+        };
+        let base = BaseProvider::new(&global, &mirror).unwrap();
+
+        assert_eq!(base.working_dir, PathBuf::from("/srv/mirrors/elv"));
+        assert_eq!(base.log_dir, PathBuf::from("/var/log/elvish-sync"));
+        assert!(!base.is_master);
+        assert_eq!(base.interval, Duration::from_secs(5 * 60));
+        assert_eq!(base.retry, 5);
+        assert_eq!(base.timeout, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn unknown_provider_should_be_rejected() {
+        let mirror = MirrorConfig {
+            provider: Some("ftp".into()),
+            ..MirrorConfig::default()
+        };
+        let err = new_provider(&WorkerGlobalConfig::default(), &mirror).err();
+        assert!(matches!(err, Some(ProviderError::Unsupported(..))));
+    }
+}
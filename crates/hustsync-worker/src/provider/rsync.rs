@@ -0,0 +1,209 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::ExitStatus;
+
+use hustsync_config_parser::{MirrorConfig, WorkerGlobalConfig};
+use hustsync_internal::util::{extract_size_from_rsync_log, translate_rsync_exit_status};
+use tracing::debug;
+
+use super::{BaseProvider, MirrorProvider, ProviderCmd, ProviderError};
+
+const RSYNC_CMD: &str = "rsync";
+
+// same defaults as tunasync
+pub(super) const DEFAULT_OPTIONS: &[&str] = &[
+    "-aHvh",
+    "--no-o",
+    "--no-g",
+    "--stats",
+    "--filter",
+    "risk .~tmp~/",
+    "--exclude",
+    ".~tmp~/",
+    "--delete",
+    "--delete-after",
+    "--delay-updates",
+    "--safe-links",
+];
+
+/// Mirrors an rsync upstream into the working directory.
+pub(crate) struct RsyncProvider {
+    base: BaseProvider,
+    options: Vec<String>,
+}
+
+impl RsyncProvider {
+    pub(crate) fn new(
+        base: BaseProvider,
+        global: &WorkerGlobalConfig,
+        mirror: &MirrorConfig,
+    ) -> Result<Self, ProviderError> {
+        check_upstream(&base)?;
+        let mut options: Vec<String> = DEFAULT_OPTIONS.iter().map(|o| o.to_string()).collect();
+        options.extend(extra_options(global, mirror));
+        Ok(RsyncProvider { base, options })
+    }
+}
+
+impl MirrorProvider for RsyncProvider {
+    fn base(&self) -> &BaseProvider {
+        &self.base
+    }
+
+    fn provider_type(&self) -> &'static str {
+        "rsync"
+    }
+
+    fn commands(&self) -> Vec<ProviderCmd> {
+        vec![rsync_cmd(&self.base, self.options.clone())]
+    }
+
+    fn check_exit_status(&self, status: &ExitStatus) -> Result<(), String> {
+        check_rsync_exit_status(&self.base, status)
+    }
+
+    fn after_sync(&self) -> Result<Option<String>, String> {
+        Ok(rsync_data_size(&self.base))
+    }
+}
+
+pub(super) fn check_upstream(base: &BaseProvider) -> Result<(), ProviderError> {
+    if base.upstream.ends_with('/') {
+        Ok(())
+    } else {
+        Err(ProviderError::InvalidConfig(
+            base.name.clone(),
+            "rsync upstream URL should ends with /".into(),
+        ))
+    }
+}
+
+/// Options appended after the provider defaults: IP family, then global and mirror options.
+pub(super) fn extra_options(global: &WorkerGlobalConfig, mirror: &MirrorConfig) -> Vec<String> {
+    let mut options = Vec::new();
+    if mirror.use_ipv6.unwrap_or(false) {
+        options.push("-6".to_string());
+    }
+    options.extend(global.rsync_options.iter().flatten().cloned());
+    options.extend(mirror.rsync_options.iter().flatten().cloned());
+    options
+}
+
+pub(super) fn rsync_cmd(base: &BaseProvider, mut args: Vec<String>) -> ProviderCmd {
+    args.push(base.upstream.clone());
+    args.push(base.working_dir.to_string_lossy().into_owned());
+    ProviderCmd {
+        program: RSYNC_CMD.to_string(),
+        args,
+        env: base.env.clone(),
+    }
+}
+
+/// Explain a failed rsync run by its exit code, noting the reason in the job log too.
+pub(super) fn check_rsync_exit_status(
+    base: &BaseProvider,
+    status: &ExitStatus,
+) -> Result<(), String> {
+    if base.is_success(status) {
+        return Ok(());
+    }
+    let (code, msg) = translate_rsync_exit_status(status);
+    let Some(msg) = msg else {
+        return Err(format!("rsync exited with {}", status));
+    };
+    debug!("Rsync exitcode {:?} ({})", code, msg);
+    if let Ok(mut log) = OpenOptions::new().append(true).open(&base.log_file) {
+        let _ = writeln!(log, "{}", msg);
+    }
+    Err(msg)
+}
+
+pub(super) fn rsync_data_size(base: &BaseProvider) -> Option<String> {
+    let log_file = base.log_file.to_string_lossy();
+    extract_size_from_rsync_log(&log_file)
+        .ok()
+        .filter(|size| !size.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn make_provider(global: WorkerGlobalConfig, mirror: MirrorConfig) -> RsyncProvider {
+        let base = BaseProvider::new(&global, &mirror).unwrap();
+        RsyncProvider::new(base, &global, &mirror).unwrap()
+    }
+
+    #[test]
+    fn rsync_command_should_use_defaults() {
+        let provider = make_provider(WorkerGlobalConfig::default(), MirrorConfig::default());
+        let cmds = provider.commands();
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].program, "rsync");
+
+        let mut expected: Vec<String> = DEFAULT_OPTIONS.iter().map(|o| o.to_string()).collect();
+        expected.push("rsync://rsync.elv.sh/elvish/".into());
+        expected.push("/tmp/tunasync/elvish".into());
+        assert_eq!(cmds[0].args, expected);
+    }
+
+    #[test]
+    fn rsync_command_should_append_options() {
+        let global = WorkerGlobalConfig {
+            rsync_options: Some(vec!["--bwlimit=100m".into()]),
+            ..WorkerGlobalConfig::default()
+        };
+        let mirror = MirrorConfig {
+            use_ipv6: Some(true),
+            rsync_options: Some(vec!["--exclude".into(), "*.iso".into()]),
+            ..MirrorConfig::default()
+        };
+        let provider = make_provider(global, mirror);
+        let args = &provider.commands()[0].args;
+        let n = DEFAULT_OPTIONS.len();
+        assert_eq!(
+            &args[n..],
+            &[
+                "-6",
+                "--bwlimit=100m",
+                "--exclude",
+                "*.iso",
+                "rsync://rsync.elv.sh/elvish/",
+                "/tmp/tunasync/elvish",
+            ]
+        );
+    }
+
+    #[test]
+    fn rsync_upstream_without_trailing_slash_should_be_rejected() {
+        let global = WorkerGlobalConfig::default();
+        let mirror = MirrorConfig {
+            upstream: Some("rsync://rsync.elv.sh/elvish".into()),
+            ..MirrorConfig::default()
+        };
+        let base = BaseProvider::new(&global, &mirror).unwrap();
+        assert!(RsyncProvider::new(base, &global, &mirror).is_err());
+    }
+
+    #[test]
+    fn rsync_exit_status_should_be_translated() {
+        let global = WorkerGlobalConfig {
+            dangerous_global_success_exit_codes: Some(vec![24]),
+            ..WorkerGlobalConfig::default()
+        };
+        let provider = make_provider(global, MirrorConfig::default());
+
+        // raw wait statuses carry the exit code in the second byte
+        assert!(provider.check_exit_status(&ExitStatus::from_raw(0)).is_ok());
+        assert!(
+            provider
+                .check_exit_status(&ExitStatus::from_raw(24 << 8))
+                .is_ok()
+        );
+        assert_eq!(
+            provider.check_exit_status(&ExitStatus::from_raw(23 << 8)),
+            Err("rsync error: Partial transfer due to error".to_string())
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::process::ExitStatus;
+
+use hustsync_config_parser::{MirrorConfig, WorkerGlobalConfig};
+
+use super::rsync::{
+    DEFAULT_OPTIONS, check_rsync_exit_status, check_upstream, extra_options, rsync_cmd,
+    rsync_data_size,
+};
+use super::{BaseProvider, MirrorProvider, ProviderCmd, ProviderError};
+
+// the first stage never deletes anything, so stale indices keep pointing at existing files
+const STAGE1_OPTIONS: &[&str] = &[
+    "-aHvh",
+    "--no-o",
+    "--no-g",
+    "--stats",
+    "--filter",
+    "risk .~tmp~/",
+    "--exclude",
+    ".~tmp~/",
+    "--safe-links",
+];
+
+const DEBIAN_PROFILE: &[&str] = &[
+    "--include=*.diff/",
+    "--include=by-hash/",
+    "--exclude=*.diff/Index",
+    "--exclude=Contents*",
+    "--exclude=Packages*",
+    "--exclude=Sources*",
+    "--exclude=Release*",
+    "--exclude=InRelease",
+    "--exclude=i18n/*",
+    "--exclude=dep11/*",
+    "--exclude=installer-*/current",
+    "--exclude=ls-lR*",
+];
+
+const DEBIAN_OLDSTYLE_PROFILE: &[&str] = &[
+    "--exclude=Packages*",
+    "--exclude=Sources*",
+    "--exclude=Release*",
+    "--exclude=InRelease",
+    "--exclude=i18n/*",
+    "--exclude=ls-lR*",
+    "--exclude=dep11/*",
+];
+
+/// Exclusion rules of the first stage, keeping the index files of an archive back.
+fn stage1_profile(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "debian" => Some(DEBIAN_PROFILE),
+        "debian-oldstyle" => Some(DEBIAN_OLDSTYLE_PROFILE),
+        _ => None,
+    }
+}
+
+/// Syncs package files first, then the indices with `--delete`, so that clients
+/// never see indices pointing at missing packages.
+pub(crate) struct TwoStageRsyncProvider {
+    base: BaseProvider,
+    stage1_options: Vec<String>,
+    stage2_options: Vec<String>,
+}
+
+impl TwoStageRsyncProvider {
+    pub(crate) fn new(
+        base: BaseProvider,
+        global: &WorkerGlobalConfig,
+        mirror: &MirrorConfig,
+    ) -> Result<Self, ProviderError> {
+        check_upstream(&base)?;
+        let profile_name = mirror.stage1_profile.as_deref().unwrap_or_default();
+        let profile = stage1_profile(profile_name).ok_or_else(|| {
+            ProviderError::InvalidConfig(
+                base.name.clone(),
+                format!("invalid stage 1 profile '{}'", profile_name),
+            )
+        })?;
+
+        let mut stage1_options: Vec<String> = STAGE1_OPTIONS
+            .iter()
+            .chain(profile)
+            .map(|o| o.to_string())
+            .collect();
+        if mirror.use_ipv6.unwrap_or(false) {
+            stage1_options.push("-6".to_string());
+        }
+
+        let mut stage2_options: Vec<String> =
+            DEFAULT_OPTIONS.iter().map(|o| o.to_string()).collect();
+        stage2_options.extend(extra_options(global, mirror));
+
+        Ok(TwoStageRsyncProvider {
+            base,
+            stage1_options,
+            stage2_options,
+        })
+    }
+}
+
+impl MirrorProvider for TwoStageRsyncProvider {
+    fn base(&self) -> &BaseProvider {
+        &self.base
+    }
+
+    fn provider_type(&self) -> &'static str {
+        "two-stage-rsync"
+    }
+
+    fn commands(&self) -> Vec<ProviderCmd> {
+        vec![
+            rsync_cmd(&self.base, self.stage1_options.clone()),
+            rsync_cmd(&self.base, self.stage2_options.clone()),
+        ]
+    }
+
+    fn check_exit_status(&self, status: &ExitStatus) -> Result<(), String> {
+        check_rsync_exit_status(&self.base, status)
+    }
+
+    fn after_sync(&self) -> Result<Option<String>, String> {
+        // the stats of the second stage come last and cover the whole mirror
+        Ok(rsync_data_size(&self.base))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debian_mirror(profile: Option<&str>) -> MirrorConfig {
+        MirrorConfig {
+            name: Some("debian".into()),
+            provider: Some("two-stage-rsync".into()),
+            upstream: Some("rsync://ftp.debian.org/debian/".into()),
+            stage1_profile: profile.map(String::from),
+            ..MirrorConfig::default()
+        }
+    }
+
+    #[test]
+    fn two_stage_rsync_should_exclude_indices_first() {
+        let global = WorkerGlobalConfig {
+            rsync_options: Some(vec!["--bwlimit=100m".into()]),
+            ..WorkerGlobalConfig::default()
+        };
+        let mirror = debian_mirror(Some("debian"));
+        let base = BaseProvider::new(&global, &mirror).unwrap();
+        let provider = TwoStageRsyncProvider::new(base, &global, &mirror).unwrap();
+        let cmds = provider.commands();
+        assert_eq!(cmds.len(), 2);
+
+        let stage1 = &cmds[0].args;
+        assert!(stage1.contains(&"--exclude=Packages*".to_string()));
+        assert!(!stage1.contains(&"--delete".to_string()));
+        assert!(!stage1.contains(&"--bwlimit=100m".to_string()));
+
+        let stage2 = &cmds[1].args;
+        assert!(stage2.contains(&"--delete".to_string()));
+        assert!(!stage2.contains(&"--exclude=Packages*".to_string()));
+        assert!(stage2.contains(&"--bwlimit=100m".to_string()));
+        for args in [stage1, stage2] {
+            assert_eq!(
+                &args[args.len() - 2..],
+                &["rsync://ftp.debian.org/debian/", "/tmp/tunasync/debian"]
+            );
+        }
+    }
+
+    #[test]
+    fn two_stage_rsync_should_reject_unknown_profile() {
+        let global = WorkerGlobalConfig::default();
+        for profile in [None, Some("ubuntu")] {
+            let mirror = debian_mirror(profile);
+            let base = BaseProvider::new(&global, &mirror).unwrap();
+            assert!(TwoStageRsyncProvider::new(base, &global, &mirror).is_err());
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+use thiserror::Error;
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::cgroup::Cgroup;
+use crate::provider::ProviderCmd;
+
+// how long a terminated command may take to clean up before it is killed
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub(crate) enum RunError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("killed")]
+    Cancelled,
+}
+
+/// Run `cmd` in `working_dir` with its output appended to `log_file`.
+///
+/// The command gets its own process group so that cancelling it also stops
+/// whatever it spawned. Given a cgroup, the command joins it before exec, so
+/// that nothing it runs escapes the limits, and the whole cgroup is killed on
+/// cancel. Failing to join the cgroup fails the command.
+pub(crate) async fn run_command(
+    cmd: &ProviderCmd,
+    working_dir: &Path,
+    log_file: &Path,
+    cgroup: Option<&Cgroup>,
+    cancel: &CancellationToken,
+) -> Result<ExitStatus, RunError> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+
+    debug!("running {} {:?}", cmd.program, cmd.args);
+    let mut command = Command::new(&cmd.program);
+    command
+        .args(&cmd.args)
+        .envs(&cmd.env)
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .process_group(0)
+        .kill_on_drop(true);
+    if let Some(cgroup) = cgroup {
+        let procs = cgroup.open_procs()?;
+        // SAFETY: between fork and exec the hook only makes a write(2) to an
+        // already open file, which neither allocates nor takes locks.
+        unsafe {
+            command.pre_exec(move || (&procs).write_all(b"0"));
+        }
+    }
+    let mut child = command.spawn()?;
+
+    tokio::select! {
+        status = child.wait() => Ok(status?),
+        _ = cancel.cancelled() => {
+            terminate(&mut child, cgroup).await;
+            Err(RunError::Cancelled)
+        }
+    }
+}
+
+async fn terminate(child: &mut Child, cgroup: Option<&Cgroup>) {
+    terminate_group(child).await;
+    // also get rid of what left the process group, e.g. daemons
+    if let Some(cgroup) = cgroup
+        && let Err(e) = cgroup.kill()
+    {
+        warn!("failed to kill cgroup: {}", e);
+    }
+}
+
+async fn terminate_group(child: &mut Child) {
+    let Some(pgid) = child.id().and_then(|id| i32::try_from(id).ok()) else {
+        return;
+    };
+    let pgid = Pid::from_raw(pgid);
+    if let Err(e) = killpg(pgid, Signal::SIGTERM) {
+        warn!("failed to terminate process group {}: {}", pgid, e);
+    }
+    if tokio::time::timeout(TERMINATE_GRACE, child.wait())
+        .await
+        .is_err()
+    {
+        let _ = killpg(pgid, Signal::SIGKILL);
+        let _ = child.wait().await;
+    }
+}
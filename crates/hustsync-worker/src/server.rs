@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use hustsync_internal::msg::WorkerCmd;
+use hustsync_internal::tls::{TlsConfig, TlsListener};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use crate::worker::{Worker, WorkerError};
+
+const ERROR_KEY: &str = "error";
+const INFO_KEY: &str = "message";
+
+/// Take commands from the manager on `listener`, over HTTPS if `tls` is set,
+/// until `shutdown` is cancelled.
+pub(crate) async fn serve(
+    worker: Arc<Worker>,
+    listener: TcpListener,
+    tls: Option<Arc<TlsConfig>>,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    match tls {
+        Some(tls) => {
+            axum::serve(TlsListener::new(listener, tls)?, router(worker))
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+        }
+        None => {
+            axum::serve(listener, router(worker))
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+        }
+    }
+}
+
+fn router(worker: Arc<Worker>) -> Router {
+    Router::new()
+        .route("/", get(ping).post(handle_cmd))
+        .with_state(worker)
+}
+
+fn error_json(code: StatusCode, msg: impl Into<String>) -> Response {
+    let msg = msg.into();
+    error!("{}", msg);
+    (code, Json(json!({ ERROR_KEY: msg }))).into_response()
+}
+
+fn info_json(msg: impl Into<String>) -> Response {
+    (StatusCode::OK, Json(json!({ INFO_KEY: msg.into() }))).into_response()
+}
+
+async fn ping() -> Response {
+    info_json("pong")
+}
+
+async fn handle_cmd(State(worker): State<Arc<Worker>>, Json(cmd): Json<WorkerCmd>) -> Response {
+    match worker.handle_cmd(&cmd).await {
+        Ok(()) => info_json("OK"),
+        Err(e) => {
+            let code = match e {
+                WorkerError::JobNotFound(_) => StatusCode::NOT_FOUND,
+                WorkerError::UnsupportedCmd(_) => StatusCode::NOT_ACCEPTABLE,
+                WorkerError::JobNotRunning(_) => StatusCode::SERVICE_UNAVAILABLE,
+                WorkerError::DuplicatedMirror(_)
+                | WorkerError::NoConfigFile
+                | WorkerError::Config(_)
+                | WorkerError::Provider(_) => StatusCode::BAD_REQUEST,
+                WorkerError::Client(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            error_json(code, e.to_string())
+        }
+    }
+}
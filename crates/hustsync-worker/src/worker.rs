@@ -0,0 +1,563 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use hustsync_config_parser::{
+    MirrorConfig, WorkerCgroupConfig, WorkerConfig, WorkerGlobalConfig, WorkerServerConfig,
+};
+use hustsync_internal::msg::{
+    CmdVerb, MirrorSchedule, MirrorSchedules, MirrorStatus, WorkerCmd, WorkerStatus,
+};
+use hustsync_internal::status::SyncStatus;
+use hustsync_internal::tls::TlsConfig;
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio::sync::{Semaphore, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::cgroup::Cgroup;
+use crate::client::{ClientError, ManagerClient, heartbeat_loop, register_with_retry};
+use crate::config::{load_config, worker_url};
+use crate::hooks::ExecHooks;
+use crate::job::{JobContext, JobCtrl, JobMsg, JobState, MirrorJob};
+use crate::provider::{ProviderError, new_provider};
+use crate::server;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+type ServeError = Box<dyn Error + Send + Sync>;
+
+#[derive(Error, Debug)]
+pub enum WorkerError {
+    #[error("duplicated mirror name: {0}")]
+    DuplicatedMirror(String),
+    #[error("job {0} not found")]
+    JobNotFound(String),
+    #[error("job {0} is not running")]
+    JobNotRunning(String),
+    #[error("unsupported command {0:?}")]
+    UnsupportedCmd(CmdVerb),
+    #[error("no config file to reload from")]
+    NoConfigFile,
+    #[error("failed to load config: {0}")]
+    Config(String),
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+    #[error(transparent)]
+    Client(#[from] ClientError),
+}
+
+struct JobHandle {
+    job: Arc<MirrorJob>,
+    // the config the job was built from, to tell changed mirrors on reload
+    mirror: MirrorConfig,
+    ctrl_tx: mpsc::UnboundedSender<JobCtrl>,
+    // taken when the job starts
+    ctrl_rx: Mutex<Option<mpsc::UnboundedReceiver<JobCtrl>>>,
+    // stops this job only, e.g. when its mirror is dropped on reload
+    stop: CancellationToken,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl JobHandle {
+    /// Spawn the job unless it already runs.
+    fn spawn(&self, ctx: &JobContext, first_run: Instant) {
+        let ctrl_rx = self
+            .ctrl_rx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let Some(ctrl_rx) = ctrl_rx else {
+            return;
+        };
+        let ctx = JobContext {
+            shutdown: self.stop.clone(),
+            ..ctx.clone()
+        };
+        let task = tokio::spawn(Arc::clone(&self.job).run(ctrl_rx, ctx, first_run));
+        *self.task.lock().unwrap_or_else(PoisonError::into_inner) = Some(task);
+    }
+
+    fn take_task(&self) -> Option<JoinHandle<()>> {
+        self.task
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+pub struct Worker {
+    name: String,
+    url: String,
+    listen_addr: (String, u16),
+    tls: Option<Arc<TlsConfig>>,
+    concurrent: usize,
+    // sections shared by the jobs, kept for those added on reload
+    global: WorkerGlobalConfig,
+    cgroup: WorkerCgroupConfig,
+    config_file: Option<PathBuf>,
+    client: Arc<ManagerClient>,
+    // in-memory job table, keyed by mirror name
+    jobs: RwLock<HashMap<String, JobHandle>>,
+    // set while the worker runs, for the jobs added on reload
+    ctx: Mutex<Option<JobContext>>,
+    shutdown: CancellationToken,
+}
+
+pub fn get_hustsync_worker(config: WorkerConfig) -> Result<Worker, Box<dyn Error>> {
+    let defaults = WorkerGlobalConfig::default();
+    let server_defaults = WorkerServerConfig::default();
+    let global = config.global.unwrap_or_default();
+    let cgroup = config.cgroup.unwrap_or_default();
+    let name = global.name.clone().or(defaults.name).unwrap_or_default();
+    let concurrent = global.concurrent.or(defaults.concurrent).unwrap_or(1);
+    let server = config.server.as_ref();
+    let listen_addr = (
+        server
+            .and_then(|s| s.listen_addr.clone())
+            .or(server_defaults.listen_addr)
+            .unwrap_or_default(),
+        server
+            .and_then(|s| s.listen_port)
+            .or(server_defaults.listen_port)
+            .unwrap_or_default(),
+    );
+    let ssl_cert = server.and_then(|s| s.ssl_cert.as_deref());
+    let ssl_key = server.and_then(|s| s.ssl_key.as_deref());
+    let tls = TlsConfig::from_pair(ssl_cert, ssl_key)?;
+    // the certificate the worker serves with is also its identity to the manager
+    let identity = tls.as_ref().and_then(|_| ssl_cert.zip(ssl_key));
+    let client = ManagerClient::new(&config.manager.unwrap_or_default(), identity)?;
+
+    let shutdown = CancellationToken::new();
+    let mut jobs = HashMap::new();
+    for mirror in config.mirrors.unwrap_or_default() {
+        let (name, handle) = make_job(&global, &cgroup, mirror, &shutdown)?;
+        if jobs.contains_key(&name) {
+            return Err(WorkerError::DuplicatedMirror(name).into());
+        }
+        jobs.insert(name, handle);
+    }
+
+    Ok(Worker {
+        name,
+        url: worker_url(server),
+        listen_addr,
+        tls: tls.map(Arc::new),
+        concurrent: usize::try_from(concurrent.max(1)).unwrap_or(1),
+        global,
+        cgroup,
+        config_file: None,
+        client: Arc::new(client),
+        jobs: RwLock::new(jobs),
+        ctx: Mutex::new(None),
+        shutdown,
+    })
+}
+
+fn make_job(
+    global: &WorkerGlobalConfig,
+    cgroup: &WorkerCgroupConfig,
+    mirror: MirrorConfig,
+    shutdown: &CancellationToken,
+) -> Result<(String, JobHandle), WorkerError> {
+    let provider = new_provider(global, &mirror)?;
+    let hooks = ExecHooks::new(global, &mirror)?;
+    let cgroup = Cgroup::new(cgroup, &mirror)?;
+    let name = provider.base().name.clone();
+    let (ctrl_tx, ctrl_rx) = mpsc::unbounded_channel();
+    let handle = JobHandle {
+        job: Arc::new(MirrorJob::new(provider, hooks, cgroup)),
+        mirror,
+        ctrl_tx,
+        ctrl_rx: Mutex::new(Some(ctrl_rx)),
+        stop: shutdown.child_token(),
+        task: Mutex::new(None),
+    };
+    Ok((name, handle))
+}
+
+impl Worker {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Remember the file the config came from, to be read again on reload.
+    ///
+    /// Unless `token_file` is configured, the token issued by the manager is
+    /// kept next to it, in `<config>.token`.
+    pub fn with_config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        if self.client.token_file().is_none() {
+            let mut token_file = path.clone().into_os_string();
+            token_file.push(".token");
+            self.client.keep_token_in(token_file);
+        }
+        self.config_file = Some(path);
+        self
+    }
+
+    /// Bind to the configured address and run until interrupted.
+    pub async fn run(self: Arc<Self>) -> Result<(), ServeError> {
+        let (addr, port) = self.listen_addr.clone();
+        let listener = TcpListener::bind((addr.as_str(), port)).await?;
+        info!("hustsync worker listening on {}:{}", addr, port);
+        self.serve(listener).await
+    }
+
+    /// Register on the manager, run every job on schedule, relay their status and
+    /// take commands on an already bound listener, until interrupted.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), ServeError> {
+        let worker = WorkerStatus {
+            id: self.name.clone(),
+            url: self.url.clone(),
+            ..WorkerStatus::default()
+        };
+        register_with_retry(Arc::clone(&self.client), worker).await?;
+        let heartbeat = tokio::spawn(heartbeat_loop(
+            Arc::clone(&self.client),
+            self.name.clone(),
+            HEARTBEAT_INTERVAL,
+        ));
+        let server = tokio::spawn(server::serve(
+            Arc::clone(&self),
+            listener,
+            self.tls.clone(),
+            self.shutdown.clone(),
+        ));
+
+        let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+        let reporter = tokio::spawn(report_loop(
+            Arc::clone(&self.client),
+            self.name.clone(),
+            msg_rx,
+        ));
+        let ctx = JobContext {
+            msg_tx,
+            semaphore: Arc::new(Semaphore::new(self.concurrent)),
+            shutdown: self.shutdown.clone(),
+        };
+        self.start_jobs(&ctx).await;
+        *self.ctx.lock().unwrap_or_else(PoisonError::into_inner) = Some(ctx);
+
+        self.wait_for_shutdown().await;
+        self.halt_jobs().await;
+        heartbeat.abort();
+        if let Err(e) = server.await? {
+            error!("worker server failed: {}", e);
+        }
+        // all status senders are gone with the jobs, so the reporter drains and exits
+        reporter.await?;
+        Ok(())
+    }
+
+    async fn halt_jobs(&self) {
+        info!("halting all jobs of worker {}", self.name);
+        self.shutdown.cancel();
+        self.ctx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        for task in self.take_tasks() {
+            if let Err(e) = task.await {
+                error!("job task failed: {}", e);
+            }
+        }
+    }
+
+    /// Wait for an interrupt, reloading the config and TLS certificate on every
+    /// SIGHUP meanwhile.
+    async fn wait_for_shutdown(&self) {
+        let mut hangup = hangup_signal();
+        loop {
+            tokio::select! {
+                _ = shutdown_signal() => return,
+                Some(()) = recv_signal(&mut hangup) => {
+                    info!("received SIGHUP, reloading config");
+                    self.reload_tls();
+                    if let Err(e) = self.reload_config().await {
+                        error!("failed to reload config: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reload_tls(&self) {
+        let Some(tls) = &self.tls else {
+            return;
+        };
+        match tls.reload() {
+            Ok(()) => info!("TLS certificate reloaded"),
+            Err(e) => error!("failed to reload TLS certificate: {}", e),
+        }
+    }
+
+    /// Spawn every job, resuming the schedule recorded on the manager if any.
+    async fn start_jobs(&self, ctx: &JobContext) {
+        let recorded = self.fetch_job_status().await;
+        let jobs = self.jobs.read().unwrap_or_else(PoisonError::into_inner);
+        for (name, handle) in jobs.iter() {
+            let first_run = match recorded.get(name) {
+                Some(status) => resume_job(&handle.job, status),
+                None => Instant::now(),
+            };
+            handle.spawn(ctx, first_run);
+        }
+    }
+
+    fn take_tasks(&self) -> Vec<JoinHandle<()>> {
+        let jobs = self.jobs.read().unwrap_or_else(PoisonError::into_inner);
+        jobs.values().filter_map(JobHandle::take_task).collect()
+    }
+
+    async fn fetch_job_status(&self) -> HashMap<String, MirrorStatus> {
+        let client = Arc::clone(&self.client);
+        let name = self.name.clone();
+        match tokio::task::spawn_blocking(move || client.list_jobs(&name)).await {
+            Ok(Ok(statuses)) => statuses.into_iter().map(|s| (s.name.clone(), s)).collect(),
+            Ok(Err(e)) => {
+                warn!("failed to fetch job status from manager: {}", e);
+                HashMap::new()
+            }
+            Err(e) => {
+                warn!("failed to fetch job status from manager: {}", e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Apply a command from the manager to the in-memory job table.
+    pub async fn handle_cmd(&self, cmd: &WorkerCmd) -> Result<(), WorkerError> {
+        if cmd.cmd == CmdVerb::Reload {
+            info!("Received command: {:?}", cmd.cmd);
+            return self.reload_config().await;
+        }
+        let jobs = self.jobs.read().unwrap_or_else(PoisonError::into_inner);
+        let handle = jobs
+            .get(&cmd.mirror_id)
+            .ok_or_else(|| WorkerError::JobNotFound(cmd.mirror_id.clone()))?;
+        let ctrl = match cmd.cmd {
+            CmdVerb::Start if cmd.options.get("force") == Some(&true) => JobCtrl::ForceStart,
+            CmdVerb::Start => JobCtrl::Start,
+            CmdVerb::Restart => JobCtrl::Restart,
+            // a disabled job has nothing to stop
+            CmdVerb::Stop if handle.job.state() == JobState::Disabled => return Ok(()),
+            CmdVerb::Stop => JobCtrl::Stop,
+            CmdVerb::Disable => JobCtrl::Disable,
+            CmdVerb::Ping => return Ok(()),
+            verb @ CmdVerb::Reload => return Err(WorkerError::UnsupportedCmd(verb)),
+        };
+        info!("Received command: {:?} {}", cmd.cmd, cmd.mirror_id);
+        handle
+            .ctrl_tx
+            .send(ctrl)
+            .map_err(|_| WorkerError::JobNotRunning(cmd.mirror_id.clone()))
+    }
+
+    /// Read the config file again and apply its mirrors.
+    pub async fn reload_config(&self) -> Result<(), WorkerError> {
+        let path = self.config_file.as_ref().ok_or(WorkerError::NoConfigFile)?;
+        let config = load_config(path).map_err(|e| WorkerError::Config(e.to_string()))?;
+        let token = config.manager.and_then(|m| m.token).unwrap_or_default();
+        self.rotate_token(token).await?;
+        self.reload(config.mirrors.unwrap_or_default()).await
+    }
+
+    // hand a token changed in the config over to the manager, so that the worker
+    // keeps reporting its mirrors without registering again
+    async fn rotate_token(&self, token: String) -> Result<(), WorkerError> {
+        if token.is_empty() || token == self.client.token() {
+            return Ok(());
+        }
+        let client = Arc::clone(&self.client);
+        let name = self.name.clone();
+        tokio::task::spawn_blocking(move || client.rotate_token(&name, &token))
+            .await
+            .map_err(ClientError::from)??;
+        info!("token of worker <{}> rotated", self.name);
+        Ok(())
+    }
+
+    /// Bring the job table in line with `mirrors`: the jobs of removed or changed
+    /// mirrors are stopped, and those of new or changed mirrors started.
+    ///
+    /// As in tunasync, only the mirrors are reloaded, the other sections keep
+    /// the values the worker started with.
+    pub async fn reload(&self, mirrors: Vec<MirrorConfig>) -> Result<(), WorkerError> {
+        let (names, fresh) = self.make_changed_jobs(mirrors)?;
+        let stale = self.remove_jobs(|name| !names.contains(name) || fresh.contains_key(name));
+        let ctx = self.job_context();
+        for (name, handle) in stale {
+            stop_job(&name, &handle).await;
+            if !names.contains(&name) {
+                info!("mirror {} removed from config", name);
+                if let Some(ctx) = &ctx {
+                    handle.job.report_disabled(ctx);
+                }
+            }
+        }
+
+        let mut jobs = self.jobs.write().unwrap_or_else(PoisonError::into_inner);
+        for (name, handle) in fresh {
+            info!("mirror {} loaded", name);
+            if let Some(ctx) = &ctx {
+                handle.spawn(ctx, Instant::now());
+            }
+            jobs.insert(name, handle);
+        }
+        Ok(())
+    }
+
+    // the names of `mirrors`, and the jobs of those new or changed, built before
+    // anything is touched so that a bad config changes nothing
+    fn make_changed_jobs(
+        &self,
+        mirrors: Vec<MirrorConfig>,
+    ) -> Result<(HashSet<String>, HashMap<String, JobHandle>), WorkerError> {
+        let jobs = self.jobs.read().unwrap_or_else(PoisonError::into_inner);
+        let mut names = HashSet::new();
+        let mut fresh = HashMap::new();
+        for mirror in mirrors {
+            let unchanged = mirror
+                .name
+                .as_ref()
+                .and_then(|n| jobs.get(n))
+                .is_some_and(|h| h.mirror == mirror);
+            let name = mirror.name.clone().unwrap_or_default();
+            if !names.insert(name.clone()) {
+                return Err(WorkerError::DuplicatedMirror(name));
+            }
+            if unchanged {
+                continue;
+            }
+            let (name, handle) = make_job(&self.global, &self.cgroup, mirror, &self.shutdown)?;
+            fresh.insert(name, handle);
+        }
+        Ok((names, fresh))
+    }
+
+    fn remove_jobs(&self, pred: impl Fn(&String) -> bool) -> Vec<(String, JobHandle)> {
+        let mut jobs = self.jobs.write().unwrap_or_else(PoisonError::into_inner);
+        let names: Vec<String> = jobs.keys().filter(|n| pred(n)).cloned().collect();
+        names
+            .into_iter()
+            .filter_map(|name| jobs.remove_entry(&name))
+            .collect()
+    }
+
+    fn job_context(&self) -> Option<JobContext> {
+        self.ctx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+// stop a job and wait for it to wind down
+async fn stop_job(name: &str, handle: &JobHandle) {
+    handle.stop.cancel();
+    if let Some(task) = handle.take_task()
+        && let Err(e) = task.await
+    {
+        error!("job task of {} failed: {}", name, e);
+    }
+}
+
+// pick up where the previous run of this worker left the job
+fn resume_job(job: &MirrorJob, status: &MirrorStatus) -> Instant {
+    match status.status {
+        SyncStatus::Disabled => {
+            job.set_state(JobState::Disabled);
+            Instant::now()
+        }
+        SyncStatus::Paused => {
+            job.set_state(JobState::Paused);
+            Instant::now()
+        }
+        _ => {
+            let next = status.last_update + job.interval();
+            let delay = (next - Utc::now()).to_std().unwrap_or_default();
+            debug!("Scheduling job {} @{}", job.name(), next);
+            Instant::now() + delay
+        }
+    }
+}
+
+async fn report_loop(
+    client: Arc<ManagerClient>,
+    worker_id: String,
+    mut msg_rx: mpsc::UnboundedReceiver<JobMsg>,
+) {
+    while let Some(msg) = msg_rx.recv().await {
+        let c = Arc::clone(&client);
+        let id = worker_id.clone();
+        let result = tokio::task::spawn_blocking(move || report(&c, &id, msg)).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("failed to update mirror status: {}", e),
+            Err(e) => error!("status report task failed: {}", e),
+        }
+    }
+}
+
+fn report(client: &ManagerClient, worker_id: &str, msg: JobMsg) -> Result<(), ClientError> {
+    match msg {
+        JobMsg::Status {
+            name,
+            upstream,
+            is_master,
+            status,
+            msg,
+            size,
+        } => {
+            let status = MirrorStatus {
+                name,
+                worker: worker_id.to_string(),
+                upstream,
+                size: size.unwrap_or_else(|| "unknown".into()),
+                error_msg: msg,
+                status,
+                is_master,
+                ..MirrorStatus::default()
+            };
+            client.update_job_status(worker_id, &status).map(|_| ())
+        }
+        JobMsg::Schedule { name, next } => {
+            let schedules = MirrorSchedules {
+                schedules: vec![MirrorSchedule {
+                    name,
+                    next_schedule: next,
+                }],
+            };
+            client.update_schedules(worker_id, &schedules)
+        }
+    }
+}
+
+fn hangup_signal() -> Option<Signal> {
+    signal(SignalKind::hangup())
+        .inspect_err(|e| error!("failed to listen for SIGHUP: {}", e))
+        .ok()
+}
+
+async fn recv_signal(sig: &mut Option<Signal>) -> Option<()> {
+    sig.as_mut()?.recv().await
+}
+
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("failed to listen for shutdown signal: {}", e);
+    }
+}
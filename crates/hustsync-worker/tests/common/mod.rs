@@ -0,0 +1,141 @@
+#![allow(dead_code, clippy::unwrap_used, clippy::expect_used)]
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hustsync_config_parser::{
+    ManagerConfig, ManagerFileConfig, ManagerServerConfig, MirrorConfig, WorkerConfig,
+    WorkerGlobalConfig, WorkerManagerConfig, WorkerServerConfig,
+};
+use hustsync_internal::msg::MirrorStatus;
+use hustsync_internal::status::SyncStatus;
+use hustsync_manager::get_hustsync_manager;
+use hustsync_worker::{Worker, get_hustsync_worker};
+use tokio::net::TcpListener;
+
+pub async fn start_test_manager() -> String {
+    start_test_manager_with_ca(None).await
+}
+
+/// Run a manager that verifies HTTPS workers against `ca_cert`.
+pub async fn start_test_manager_with_ca(ca_cert: Option<String>) -> String {
+    serve_test_manager(ManagerServerConfig::default(), ca_cert).await
+}
+
+/// Run a manager that turns away worker calls without the worker token.
+pub async fn start_test_manager_requiring_tokens() -> String {
+    let server = ManagerServerConfig {
+        require_worker_token: Some(true),
+        ..ManagerServerConfig::default()
+    };
+    serve_test_manager(server, None).await
+}
+
+async fn serve_test_manager(server: ManagerServerConfig, ca_cert: Option<String>) -> String {
+    let config = ManagerConfig {
+        server: Some(server),
+        files: Some(ManagerFileConfig {
+            status_file: None,
+            db_type: Some("memory".into()),
+            db_file: None,
+            ca_cert,
+        }),
+        debug: Some(false),
+    };
+
+    let manager = get_hustsync_manager(config).expect("create manager");
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(manager.serve(listener));
+    format!("http://{}", addr)
+}
+
+pub fn make_worker_config(
+    api_base: &str,
+    work_dir: &Path,
+    mirrors: Vec<MirrorConfig>,
+) -> WorkerConfig {
+    WorkerConfig {
+        global: Some(WorkerGlobalConfig {
+            name: Some("test_worker".into()),
+            log_dir: Some(
+                work_dir
+                    .join("log/{{.Name}}")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            mirror_dir: Some(work_dir.join("mirrors").to_string_lossy().into_owned()),
+            ..WorkerGlobalConfig::default()
+        }),
+        manager: Some(WorkerManagerConfig {
+            api_base: Some(api_base.to_string()),
+            token: None,
+            ca_cert: None,
+            token_file: None,
+        }),
+        cgroup: None,
+        server: None,
+        mirrors: Some(mirrors),
+    }
+}
+
+/// Run a worker built from `config` on a free local port, returning it with its URL.
+///
+/// Only the TLS options of `config.server` are kept.
+pub async fn start_worker(mut config: WorkerConfig) -> (Arc<Worker>, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let port = listener.local_addr().expect("local addr").port();
+    let (ssl_cert, ssl_key) = config
+        .server
+        .take()
+        .map(|s| (s.ssl_cert, s.ssl_key))
+        .unwrap_or_default();
+    config.server = Some(WorkerServerConfig {
+        hostname: Some("127.0.0.1".into()),
+        listen_addr: Some("127.0.0.1".into()),
+        listen_port: Some(port),
+        ssl_cert,
+        ssl_key,
+    });
+
+    // the blocking HTTP client of the worker must not be created in async context
+    let worker =
+        tokio::task::spawn_blocking(move || get_hustsync_worker(config).map_err(|e| e.to_string()))
+            .await
+            .expect("join")
+            .expect("create worker");
+    let worker = Arc::new(worker);
+    let url = worker.url().to_string();
+    tokio::spawn(Arc::clone(&worker).serve(listener));
+    (worker, url)
+}
+
+/// Poll the manager until `mirror` of `test_worker` reaches `status`, giving up after 10s.
+pub async fn wait_for_status(
+    base_url: &str,
+    mirror: &str,
+    status: SyncStatus,
+) -> Option<MirrorStatus> {
+    wait_for_job(base_url, mirror, |job| job.status == status).await
+}
+
+/// Poll the manager until `mirror` of `test_worker` satisfies `done`, giving up after 10s.
+pub async fn wait_for_job(
+    base_url: &str,
+    mirror: &str,
+    done: impl Fn(&MirrorStatus) -> bool,
+) -> Option<MirrorStatus> {
+    let url = format!("{}/workers/test_worker/jobs", base_url);
+    for _ in 0..100 {
+        if let Ok(resp) = reqwest::get(&url).await
+            && let Ok(jobs) = resp.json::<Vec<MirrorStatus>>().await
+            && let Some(job) = jobs.into_iter().find(|j| j.name == mirror)
+            && done(&job)
+        {
+            return Some(job);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    None
+}
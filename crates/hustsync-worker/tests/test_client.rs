@@ -0,0 +1,150 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+
+use hustsync_config_parser::WorkerManagerConfig;
+use hustsync_internal::msg::WorkerStatus;
+use hustsync_worker::{ManagerClient, register_with_retry};
+
+mod common;
+use common::{start_test_manager, start_test_manager_requiring_tokens};
+
+fn make_client(api_base: &str) -> ManagerClient {
+    ManagerClient::new(
+        &WorkerManagerConfig {
+            api_base: Some(api_base.to_string()),
+            token: None,
+            ca_cert: Some("".into()),
+            token_file: None,
+        },
+        None,
+    )
+    .expect("create manager client")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn register_and_heartbeat_should_work() {
+    let base_url = start_test_manager().await;
+    let client = Arc::new(
+        tokio::task::spawn_blocking(move || make_client(&base_url))
+            .await
+            .unwrap(),
+    );
+
+    let worker = WorkerStatus {
+        id: "test_worker".into(),
+        url: "http://localhost:6000/".into(),
+        ..WorkerStatus::default()
+    };
+    let registered = register_with_retry(Arc::clone(&client), worker)
+        .await
+        .unwrap();
+    assert_eq!(registered.id, "test_worker");
+    assert_eq!(registered.last_online, registered.last_register);
+
+    let c = Arc::clone(&client);
+    let refreshed = tokio::task::spawn_blocking(move || c.heartbeat("test_worker"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(refreshed.last_online >= registered.last_online);
+    assert_eq!(refreshed.last_register, registered.last_register);
+
+    let c = Arc::clone(&client);
+    let unknown = tokio::task::spawn_blocking(move || c.heartbeat("no_such_worker"))
+        .await
+        .unwrap();
+    assert!(unknown.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn issued_token_should_be_kept_and_rotated() {
+    let base_url = start_test_manager().await;
+    let client = Arc::new(
+        tokio::task::spawn_blocking(move || make_client(&base_url))
+            .await
+            .unwrap(),
+    );
+    let worker = WorkerStatus {
+        id: "test_worker".into(),
+        ..WorkerStatus::default()
+    };
+    let registered = register_with_retry(Arc::clone(&client), worker)
+        .await
+        .unwrap();
+    assert!(!registered.token.is_empty());
+    assert_eq!(client.token(), registered.token);
+
+    let c = Arc::clone(&client);
+    tokio::task::spawn_blocking(move || {
+        c.rotate_token("test_worker", "rotated").unwrap();
+        c.heartbeat("test_worker").unwrap();
+    })
+    .await
+    .unwrap();
+    assert_eq!(client.token(), "rotated");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn issued_token_should_survive_a_worker_restart() {
+    let base_url = start_test_manager_requiring_tokens().await;
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let token_file = tmp_dir.path().join("worker.conf.token");
+    let worker = WorkerStatus {
+        id: "test_worker".into(),
+        ..WorkerStatus::default()
+    };
+    let start = {
+        let base_url = base_url.clone();
+        let token_file = token_file.clone();
+        move |keep_token: bool| {
+            let client = make_client(&base_url);
+            if keep_token {
+                client.keep_token_in(&token_file);
+            }
+            Arc::new(client)
+        }
+    };
+
+    let first = tokio::task::spawn_blocking({
+        let start = start.clone();
+        move || start(true)
+    })
+    .await
+    .unwrap();
+    let registered = register_with_retry(first, worker.clone()).await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&token_file).unwrap(),
+        registered.token
+    );
+    let mode = std::fs::metadata(&token_file).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // a restarted worker that lost its token cannot take its name back
+    let forgetful = tokio::task::spawn_blocking({
+        let start = start.clone();
+        move || start(false)
+    })
+    .await
+    .unwrap();
+    let w = worker.clone();
+    let resp = tokio::task::spawn_blocking(move || forgetful.register_worker(&w))
+        .await
+        .unwrap();
+    assert!(resp.is_err());
+
+    let restarted = tokio::task::spawn_blocking(move || start(true))
+        .await
+        .unwrap();
+    assert_eq!(restarted.token(), registered.token);
+    let again = register_with_retry(Arc::clone(&restarted), worker)
+        .await
+        .unwrap();
+    assert_eq!(again.token, registered.token);
+    let c = Arc::clone(&restarted);
+    tokio::task::spawn_blocking(move || c.heartbeat("test_worker").unwrap())
+        .await
+        .unwrap();
+}
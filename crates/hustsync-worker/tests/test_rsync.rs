@@ -0,0 +1,84 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use hustsync_config_parser::MirrorConfig;
+use hustsync_internal::status::SyncStatus;
+
+mod common;
+use common::{make_worker_config, start_test_manager, start_worker, wait_for_status};
+
+// stands in for rsync: echoes its arguments and prints a stats summary
+const FAKE_RSYNC: &str = r#"#!/bin/sh
+echo "args: $*"
+echo "Number of files: 1,234"
+echo "Total file size: 1.33T bytes"
+exit "${FAKE_RSYNC_EXIT:-0}"
+"#;
+
+fn install_fake_rsync(dir: &Path) -> String {
+    let bin_dir = dir.join("bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+    let rsync = bin_dir.join("rsync");
+    fs::write(&rsync, FAKE_RSYNC).unwrap();
+    fs::set_permissions(&rsync, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    format!("{}:{}", bin_dir.display(), path)
+}
+
+fn rsync_mirror(name: &str, path: &str, exit_code: i32) -> MirrorConfig {
+    let env = HashMap::from([
+        ("PATH".to_string(), path.to_string()),
+        ("FAKE_RSYNC_EXIT".to_string(), exit_code.to_string()),
+    ]);
+    MirrorConfig {
+        name: Some(name.into()),
+        provider: Some("rsync".into()),
+        upstream: Some(format!("rsync://rsync.example.com/{}/", name)),
+        use_ipv6: Some(true),
+        env: Some(env),
+        ..MirrorConfig::default()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rsync_job_should_report_size_and_errors() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let path = install_fake_rsync(work_dir.path());
+    let config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![
+            rsync_mirror("good", &path, 0),
+            rsync_mirror("partial", &path, 23),
+        ],
+    );
+
+    start_worker(config).await;
+
+    let good = wait_for_status(&base_url, "good", SyncStatus::Success)
+        .await
+        .expect("good should succeed");
+    assert_eq!(good.size, "1.33T");
+    let log = fs::read_to_string(work_dir.path().join("log/good/latest.log")).unwrap();
+    let mirror_dir = work_dir.path().join("mirrors/good");
+    assert!(log.contains(&format!(
+        "-6 rsync://rsync.example.com/good/ {}",
+        mirror_dir.display()
+    )));
+
+    let partial = wait_for_status(&base_url, "partial", SyncStatus::Failed)
+        .await
+        .expect("partial should fail");
+    assert_eq!(
+        partial.error_msg,
+        "rsync error: Partial transfer due to error"
+    );
+    let log = fs::read_to_string(work_dir.path().join("log/partial/latest.log")).unwrap();
+    assert!(log.ends_with("rsync error: Partial transfer due to error\n"));
+}
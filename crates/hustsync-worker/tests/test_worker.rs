@@ -0,0 +1,508 @@
+#![cfg(test)]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use hustsync_config_parser::{
+    ExecOnStatus, ExecOnStatusExtra, MirrorConfig, RetryStrategy, WorkerCgroupConfig,
+    WorkerServerConfig,
+};
+use hustsync_internal::msg::{CmdVerb, WorkerCmd};
+use hustsync_internal::status::SyncStatus;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+
+mod common;
+use common::{
+    make_worker_config, start_test_manager, start_test_manager_with_ca, start_worker, wait_for_job,
+    wait_for_status,
+};
+
+fn command_mirror(name: &str, command: &str) -> MirrorConfig {
+    MirrorConfig {
+        name: Some(name.into()),
+        provider: Some("command".into()),
+        upstream: Some("https://example.com/".into()),
+        command: Some(command.into()),
+        ..MirrorConfig::default()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn worker_should_sync_and_obey_commands() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![
+            command_mirror("quick", "sh -c 'echo synced quick'"),
+            command_mirror("broken", "sh -c 'echo oops; exit 3'"),
+        ],
+    );
+
+    let (worker, _) = start_worker(config).await;
+
+    let quick = wait_for_status(&base_url, "quick", SyncStatus::Success)
+        .await
+        .expect("quick should succeed");
+    assert_eq!(quick.worker, "test_worker");
+    assert_eq!(quick.upstream, "https://example.com/");
+    assert!(quick.is_master);
+    let log = fs::read_to_string(work_dir.path().join("log/quick/latest.log")).unwrap();
+    assert_eq!(log, "synced quick\n");
+    assert!(work_dir.path().join("mirrors/quick").is_dir());
+
+    let broken = wait_for_status(&base_url, "broken", SyncStatus::Failed)
+        .await
+        .expect("broken should fail");
+    assert!(broken.error_msg.contains("exit status: 3"));
+
+    worker
+        .handle_cmd(&WorkerCmd {
+            options: HashMap::new(),
+            args: Vec::new(),
+            mirror_id: "quick".into(),
+            cmd: CmdVerb::Disable,
+        })
+        .await
+        .unwrap();
+    wait_for_status(&base_url, "quick", SyncStatus::Disabled)
+        .await
+        .expect("quick should be disabled");
+
+    let unknown = worker
+        .handle_cmd(&WorkerCmd {
+            options: HashMap::new(),
+            args: Vec::new(),
+            mirror_id: "no_such_mirror".into(),
+            cmd: CmdVerb::Start,
+        })
+        .await;
+    assert!(unknown.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn command_job_should_apply_log_patterns() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![
+            MirrorConfig {
+                size_pattern: Some(r"size: ([0-9\.]+[KMGTP]?)".into()),
+                ..command_mirror(
+                    "sized",
+                    r#"sh -c 'echo "$TUNASYNC_MIRROR_NAME $TUNASYNC_UPSTREAM_URL"; echo size: 12.5G'"#,
+                )
+            },
+            MirrorConfig {
+                fail_on_match: Some("(?m)^ERROR".into()),
+                ..command_mirror("flaky", "sh -c 'echo ERROR: timed out; echo ERROR: again'")
+            },
+        ],
+    );
+
+    start_worker(config).await;
+
+    let sized = wait_for_status(&base_url, "sized", SyncStatus::Success)
+        .await
+        .expect("sized should succeed");
+    assert_eq!(sized.size, "12.5G");
+    let log = fs::read_to_string(work_dir.path().join("log/sized/latest.log")).unwrap();
+    assert!(log.starts_with("sized https://example.com/\n"));
+
+    let flaky = wait_for_status(&base_url, "flaky", SyncStatus::Failed)
+        .await
+        .expect("flaky should fail");
+    assert_eq!(flaky.error_msg, "Fail-on-match regexp found 2 matches");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn job_should_retry_and_time_out() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![
+            MirrorConfig {
+                retry: Some(RetryStrategy {
+                    retry: Some(3),
+                    timeout: None,
+                    interval: None,
+                }),
+                // fails once, then succeeds
+                ..command_mirror(
+                    "flaky",
+                    r#"sh -c 'echo attempt >> tries; echo attempt; [ "$(wc -l < tries)" -ge 2 ]'"#,
+                )
+            },
+            MirrorConfig {
+                retry: Some(RetryStrategy {
+                    retry: None,
+                    timeout: Some(1),
+                    interval: None,
+                }),
+                ..command_mirror("slow", "sleep 30")
+            },
+        ],
+    );
+
+    start_worker(config).await;
+
+    wait_for_status(&base_url, "flaky", SyncStatus::Success)
+        .await
+        .expect("flaky should succeed on retry");
+    let log = fs::read_to_string(work_dir.path().join("log/flaky/latest.log")).unwrap();
+    assert_eq!(log, "attempt\nattempt\n");
+
+    let slow = wait_for_status(&base_url, "slow", SyncStatus::Failed)
+        .await
+        .expect("slow should time out");
+    assert_eq!(slow.error_msg, "slow timeout after 1s");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hooks_should_run_after_sync() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let record = |file: &str| {
+        format!(
+            r#"sh -c 'echo "$TUNASYNC_MIRROR_NAME $TUNASYNC_JOB_EXIT_STATUS" >> ../{}'"#,
+            file
+        )
+    };
+    let mut config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![
+            MirrorConfig {
+                exec_on_status_extra: Some(ExecOnStatusExtra {
+                    exec_on_success_extra: Some(vec![record("extra")]),
+                    exec_on_failure_extra: None,
+                }),
+                ..command_mirror("good", "true")
+            },
+            MirrorConfig {
+                exec_on_status: Some(ExecOnStatus {
+                    exec_on_success: None,
+                    exec_on_failure: Some(vec![record("own")]),
+                }),
+                retry: Some(RetryStrategy {
+                    retry: Some(1),
+                    timeout: None,
+                    interval: None,
+                }),
+                ..command_mirror("bad", "false")
+            },
+        ],
+    );
+    if let Some(global) = config.global.as_mut() {
+        global.exec_on_status = Some(ExecOnStatus {
+            exec_on_success: Some(vec![record("global")]),
+            exec_on_failure: Some(vec![record("global")]),
+        });
+    }
+
+    start_worker(config).await;
+
+    wait_for_status(&base_url, "good", SyncStatus::Success)
+        .await
+        .expect("good should succeed");
+    wait_for_status(&base_url, "bad", SyncStatus::Failed)
+        .await
+        .expect("bad should fail");
+
+    let mirrors = work_dir.path().join("mirrors");
+    let read = |file: &str| fs::read_to_string(mirrors.join(file)).unwrap_or_default();
+    assert_eq!(read("global"), "good success\n");
+    assert_eq!(read("extra"), "good success\n");
+    assert_eq!(read("own"), "bad failure\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn force_start_should_exceed_concurrent_limit() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let mut config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![
+            command_mirror("first", "sleep 30"),
+            command_mirror("second", "sleep 30"),
+        ],
+    );
+    if let Some(global) = config.global.as_mut() {
+        global.concurrent = Some(1);
+    }
+
+    let (worker, _) = start_worker(config).await;
+
+    // one of the jobs syncs while the other waits for it
+    let url = format!("{}/workers/test_worker/jobs", base_url);
+    let mut waiting = None;
+    for _ in 0..100 {
+        // an error until the worker has registered
+        let jobs: Vec<Value> = reqwest::get(&url)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap_or_default();
+        if let Some(job) = jobs.iter().find(|j| j["status"] == "syncing") {
+            waiting = Some(if job["name"] == "first" {
+                "second"
+            } else {
+                "first"
+            });
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let waiting = waiting.expect("a job should be syncing");
+    let cmd = |cmd, options| WorkerCmd {
+        options,
+        args: Vec::new(),
+        mirror_id: waiting.into(),
+        cmd,
+    };
+    worker
+        .handle_cmd(&cmd(CmdVerb::Stop, HashMap::new()))
+        .await
+        .unwrap();
+    wait_for_status(&base_url, waiting, SyncStatus::Paused)
+        .await
+        .expect("the waiting job should be paused");
+
+    worker
+        .handle_cmd(&cmd(
+            CmdVerb::Start,
+            HashMap::from([("force".into(), true)]),
+        ))
+        .await
+        .unwrap();
+    wait_for_status(&base_url, waiting, SyncStatus::Syncing)
+        .await
+        .expect("the forced job should sync beside the other");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn job_should_run_in_its_cgroup() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let cgroup_root = tempfile::tempdir().unwrap();
+    let mut config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![MirrorConfig {
+            memory_limit: Some("64M".into()),
+            ..command_mirror("capped", "sleep 30")
+        }],
+    );
+    config.cgroup = Some(WorkerCgroupConfig {
+        enable: Some(true),
+        base_path: Some(cgroup_root.path().to_string_lossy().into_owned()),
+        group: Some("hustsync".into()),
+    });
+
+    let (worker, _) = start_worker(config).await;
+
+    wait_for_status(&base_url, "capped", SyncStatus::Syncing)
+        .await
+        .expect("capped should be syncing");
+    let cgroup = cgroup_root.path().join("hustsync/capped");
+    // the status is reported just before the command is spawned
+    for _ in 0..50 {
+        if cgroup.join("cgroup.procs").exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        fs::read_to_string(cgroup.join("memory.max")).unwrap(),
+        "67108864"
+    );
+    // written by the command itself before exec
+    assert_eq!(
+        fs::read_to_string(cgroup.join("cgroup.procs")).unwrap(),
+        "0"
+    );
+
+    worker
+        .handle_cmd(&WorkerCmd {
+            options: HashMap::new(),
+            args: Vec::new(),
+            mirror_id: "capped".into(),
+            cmd: CmdVerb::Stop,
+        })
+        .await
+        .unwrap();
+    wait_for_status(&base_url, "capped", SyncStatus::Paused)
+        .await
+        .expect("capped should be paused");
+    assert_eq!(fs::read_to_string(cgroup.join("cgroup.kill")).unwrap(), "1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn job_should_fail_when_it_cannot_join_its_cgroup() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let cgroup_root = tempfile::tempdir().unwrap();
+    let mut config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![command_mirror("escaped", "true")],
+    );
+    config.cgroup = Some(WorkerCgroupConfig {
+        enable: Some(true),
+        base_path: Some(cgroup_root.path().to_string_lossy().into_owned()),
+        group: Some("hustsync".into()),
+    });
+    // cgroup.procs cannot be opened for writing
+    fs::create_dir_all(cgroup_root.path().join("hustsync/escaped/cgroup.procs")).unwrap();
+
+    let (_worker, _) = start_worker(config).await;
+
+    wait_for_status(&base_url, "escaped", SyncStatus::Failed)
+        .await
+        .expect("escaped should fail");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn worker_should_take_commands_over_http() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![command_mirror("slow", "sleep 30")],
+    );
+    let (_worker, worker_url) = start_worker(config).await;
+    wait_for_status(&base_url, "slow", SyncStatus::Syncing)
+        .await
+        .expect("slow should be syncing");
+
+    let client = reqwest::Client::new();
+    let post_cmd = |mirror: &str, cmd: &str| {
+        client.post(&worker_url).json(&json!({
+            "cmd": cmd,
+            "mirror_id": mirror,
+            "args": [],
+            "options": {},
+        }))
+    };
+
+    let resp = post_cmd("slow", "ping").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["message"], "OK");
+
+    let resp = post_cmd("no_such_mirror", "start").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "job no_such_mirror not found");
+
+    let resp = post_cmd("", "reload").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "no config file to reload from");
+
+    // relayed by the manager
+    let resp = client
+        .post(format!("{}/cmd", base_url))
+        .json(&json!({
+            "cmd": "stop",
+            "worker_id": "test_worker",
+            "mirror_id": "slow",
+            "args": [],
+            "options": {},
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // the manager marks the job paused before the worker reports it killed
+    wait_for_job(&base_url, "slow", |j| {
+        j.status == SyncStatus::Paused && j.error_msg == "killed by manager"
+    })
+    .await
+    .expect("slow should be killed by manager");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn worker_should_take_commands_over_https() {
+    let cert_dir = tempfile::tempdir().unwrap();
+    let certified =
+        rcgen::generate_simple_self_signed(vec!["localhost".into(), "127.0.0.1".into()]).unwrap();
+    let cert_file = cert_dir.path().join("cert.pem");
+    let key_file = cert_dir.path().join("key.pem");
+    fs::write(&cert_file, certified.cert.pem()).unwrap();
+    fs::write(&key_file, certified.signing_key.serialize_pem()).unwrap();
+
+    let base_url = start_test_manager_with_ca(Some(cert_file.to_string_lossy().into_owned())).await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let mut config = make_worker_config(
+        &base_url,
+        work_dir.path(),
+        vec![command_mirror("slow", "sleep 30")],
+    );
+    config.server = Some(WorkerServerConfig {
+        ssl_cert: Some(cert_file.to_string_lossy().into_owned()),
+        ssl_key: Some(key_file.to_string_lossy().into_owned()),
+        ..WorkerServerConfig::default()
+    });
+    let (_worker, worker_url) = start_worker(config).await;
+    assert!(worker_url.starts_with("https://"));
+    wait_for_status(&base_url, "slow", SyncStatus::Syncing)
+        .await
+        .expect("slow should be syncing");
+
+    // the manager trusts the worker certificate through its ca_cert
+    let resp = reqwest::Client::new()
+        .post(format!("{}/cmd", base_url))
+        .json(&json!({
+            "cmd": "stop",
+            "worker_id": "test_worker",
+            "mirror_id": "slow",
+            "args": [],
+            "options": {},
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    wait_for_status(&base_url, "slow", SyncStatus::Paused)
+        .await
+        .expect("slow should be paused");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_should_update_job_table() {
+    let base_url = start_test_manager().await;
+    let work_dir = tempfile::tempdir().unwrap();
+    let quick = || command_mirror("quick", "true");
+    let added = || command_mirror("added", "true");
+    let config = make_worker_config(&base_url, work_dir.path(), vec![quick()]);
+    let (worker, _) = start_worker(config).await;
+    wait_for_status(&base_url, "quick", SyncStatus::Success)
+        .await
+        .expect("quick should succeed");
+
+    worker.reload(vec![quick(), added()]).await.unwrap();
+    wait_for_status(&base_url, "added", SyncStatus::Success)
+        .await
+        .expect("added should succeed");
+
+    let duplicated = worker.reload(vec![added(), added()]).await;
+    assert!(duplicated.is_err());
+
+    worker.reload(vec![added()]).await.unwrap();
+    let quick = wait_for_status(&base_url, "quick", SyncStatus::Disabled)
+        .await
+        .expect("quick should be disabled");
+    assert_eq!(quick.error_msg, "removed from config");
+}